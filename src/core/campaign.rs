@@ -0,0 +1,171 @@
+// See LICENSE file for copyright and license details.
+
+use std::io::File;
+use std::io::fs::PathExtensions;
+use serialize::json;
+use core::types::{MInt, PlayerId};
+use core::misc::read_file;
+use core::fs::FileSystem;
+use core::core::Core;
+
+pub fn get_save_path(fs: &FileSystem) -> Path {
+    fs.get(&Path::new("campaign_progress.json"))
+}
+
+pub fn get_campaign_path(fs: &FileSystem) -> Path {
+    fs.get(&Path::new("data/campaign.json"))
+}
+
+// Campaign file is not part of the data repo and must be copied by hand
+pub fn is_campaign_available(fs: &FileSystem) -> bool {
+    get_campaign_path(fs).exists()
+}
+
+fn to_campaign_units(names: &Vec<String>) -> Vec<CampaignUnit> {
+    names.iter().map(|name| CampaignUnit::new(name.as_slice())).collect()
+}
+
+// Unit that is carried over between battles
+#[deriving(Encodable, Decodable, Clone)]
+pub struct CampaignUnit {
+    pub type_name: String,
+    pub experience: MInt,
+}
+
+impl CampaignUnit {
+    pub fn new(type_name: &str) -> CampaignUnit {
+        CampaignUnit {
+            type_name: type_name.to_string(),
+            experience: 0,
+        }
+    }
+}
+
+#[deriving(Decodable)]
+pub struct CampaignMission {
+    pub name: String,
+    pub scenario: String, // see Scenario::from_name
+    pub new_units: Vec<String>, // join player's army before the battle
+    pub enemy_units: Vec<String>,
+    pub next_on_victory: Option<uint>, // None ends the campaign
+    pub next_on_defeat: Option<uint>,
+}
+
+#[deriving(Decodable)]
+pub struct Campaign {
+    pub missions: Vec<CampaignMission>,
+}
+
+impl Campaign {
+    pub fn load(path: &Path) -> Campaign {
+        let campaign: Campaign = match json::decode(read_file(path).as_slice()) {
+            Ok(campaign) => campaign,
+            Err(msg) => panic!("Can not load campaign {}: {}", path.display(), msg),
+        };
+        campaign.check();
+        campaign
+    }
+
+    // Next missions must go later in the list so the campaign always ends
+    fn check(&self) {
+        for (index, mission) in self.missions.iter().enumerate() {
+            let next_indices = [mission.next_on_victory, mission.next_on_defeat];
+            for next_index in next_indices.iter() {
+                match *next_index {
+                    Some(next_index) if next_index <= index
+                        || next_index >= self.missions.len() =>
+                    {
+                        panic!("Bad next mission of \"{}\": {}",
+                            mission.name, next_index);
+                    },
+                    _ => {},
+                }
+            }
+        }
+    }
+
+    pub fn get_mission<'a>(&'a self, index: uint) -> &'a CampaignMission {
+        &self.missions[index]
+    }
+}
+
+// Campaign is played by player 0
+#[deriving(Encodable, Decodable, Clone)]
+pub struct CampaignProgress {
+    pub mission_index: Option<uint>, // None if campaign is finished
+    pub units: Vec<CampaignUnit>,
+    pub resources: MInt,
+    pub victories: MInt,
+}
+
+impl CampaignProgress {
+    pub fn new() -> CampaignProgress {
+        CampaignProgress {
+            mission_index: Some(0),
+            units: Vec::new(),
+            resources: 0,
+            victories: 0,
+        }
+    }
+
+    // Starts new campaign if there is no save file
+    pub fn load(path: &Path) -> CampaignProgress {
+        if !path.exists() {
+            return CampaignProgress::new();
+        }
+        match json::decode(read_file(path).as_slice()) {
+            Ok(progress) => progress,
+            Err(msg) => panic!("Can not load campaign {}: {}", path.display(), msg),
+        }
+    }
+
+    pub fn save(&self, path: &Path) {
+        let mut file = match File::create(path) {
+            Ok(file) => file,
+            Err(msg) => panic!("Can not create {}: {}", path.display(), msg),
+        };
+        match file.write_str(json::encode(self).as_slice()) {
+            Ok(_) => {},
+            Err(msg) => panic!("Can not save campaign {}: {}", path.display(), msg),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.mission_index.is_none()
+    }
+
+    // Creates battle of the next mission with filled deployment rosters
+    pub fn create_core(&self, fs: &FileSystem, campaign: &Campaign) -> Core {
+        let mission = campaign.get_mission(self.mission_index.unwrap());
+        let mut core = Core::new_with_scenario(fs, mission.scenario.as_slice());
+        let player_id = PlayerId{id: 0};
+        let mut units = self.units.clone();
+        units.push_all_move(to_campaign_units(&mission.new_units));
+        core.import_units(player_id, &units);
+        core.import_units(PlayerId{id: 1}, &to_campaign_units(&mission.enemy_units));
+        let resources = core.export_resources(player_id) + self.resources;
+        core.import_resources(player_id, resources);
+        core
+    }
+
+    // Survivors replace the old army
+    pub fn finish_mission(
+        &mut self,
+        campaign: &Campaign,
+        is_victory: bool,
+        units: Vec<CampaignUnit>,
+        resources: MInt,
+    ) {
+        let mission = campaign.get_mission(self.mission_index.unwrap());
+        self.mission_index = if is_victory {
+            self.victories += 1;
+            mission.next_on_victory
+        } else {
+            mission.next_on_defeat
+        };
+        self.units = units;
+        self.resources = resources;
+    }
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
@@ -34,6 +34,14 @@ impl Config {
             None => panic!("No field '{}", name),
         })
     }
+
+    pub fn get_opt<A: Decodable<json::Decoder, json::DecoderError>>(
+        &self,
+        name: &str,
+    ) -> Option<A> {
+        let owned_name_str = name.into_string();
+        self.json.find(&owned_name_str).map(|val| decode(val.clone()))
+    }
 }
 
 // vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
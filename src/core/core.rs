@@ -1,20 +1,57 @@
 // See LICENSE file for copyright and license details.
 
 use std::rand::{task_rng, Rng};
+use std::cmp::{max, min};
 use std::collections::hashmap::HashMap;
-use cgmath::{Vector2};
 use error_context;
-use core::types::{Size2, MInt, UnitId, PlayerId, MapPos};
+use core::types::{Size2, MInt, UnitId, PlayerId, MapPos, ObjectiveId};
 use core::conf::Config;
 use core::game_state::GameState;
 use core::fs::FileSystem;
-use core::map::{distance};
+use core::map::{MapPosIter, distance};
+use core::rules::Rules;
+use core::los::is_los_clear;
+use core::scenario::{
+    Scenario,
+    TriggerCondition,
+    TriggerAction,
+    ConditionRound,
+    ConditionUnitInArea,
+    ConditionUnitTypeDestroyed,
+    ConditionObjectiveCaptured,
+    ActionSpawnUnits,
+    ActionShowMessage,
+    ActionChangeObjective,
+    ActionGrantResources,
+    ActionEndGame,
+};
+use core::pathfinder::Pathfinder;
+use core::campaign::CampaignUnit;
 
 pub enum Command {
     CommandMove(UnitId, Vec<MapPos>),
     CommandEndTurn,
-    CommandCreateUnit(MapPos),
-    CommandAttackUnit(UnitId, UnitId),
+    CommandBuyUnit(UnitTypeId, MapPos),
+    CommandAttackUnit(UnitId, UnitId, WeaponChoice),
+    CommandAttackStack(UnitId, MapPos, WeaponChoice), // random unit in tile
+    CommandLoadUnit(UnitId, UnitId), // passenger_id, transporter_id
+    CommandUnloadUnit(UnitId, MapPos),
+    CommandAttackPos(UnitId, MapPos, /* weapon_index: */ MInt),
+    CommandLayMines(UnitId, MapPos),
+    CommandClearMines(UnitId, MapPos),
+    CommandFortify(UnitId),
+    CommandHealUnit(UnitId, UnitId), // healer_id, unit_id
+    CommandDeployUnit(UnitTypeId, MapPos),
+    CommandReady, // ends deployment of current player
+    CommandUndo, // cancels last move if it revealed nothing
+    CommandQueueMove(UnitId, Vec<MapPos>), // path can be longer than one turn move
+    CommandMoveGroup(Vec<(UnitId, Vec<MapPos>)>), // moves are done in this order
+}
+
+#[deriving(Clone)]
+pub enum WeaponChoice {
+    BestWeapon,
+    WeaponIndex(MInt),
 }
 
 #[deriving(Clone)]
@@ -22,13 +59,166 @@ pub enum Event {
     EventMove(UnitId, Vec<MapPos>),
     EventEndTurn(PlayerId, PlayerId), // old_id, new_id
     EventCreateUnit(UnitId, MapPos, UnitTypeId, PlayerId),
-    EventAttackUnit(UnitId, UnitId, /* killed: */ bool),
+    EventAttackUnit(UnitId, UnitId, /* weapon_index: */ MInt, /* damage: */ MInt),
+    EventDamageVehicle(UnitId, VehicleDamage),
+    EventChangeResources(PlayerId, /* diff: */ MInt),
+    EventCaptureObjective(ObjectiveId, PlayerId),
+    EventChangeScore(PlayerId, /* diff: */ MInt),
+    EventVictory(PlayerId),
+    EventSuppressUnit(UnitId, /* suppression: */ MInt, MoraleState),
+    EventPromoteUnit(UnitId, /* rank: */ MInt),
+    EventLoadUnit(UnitId, UnitId), // passenger_id, transporter_id
+    EventUnloadUnit(UnitId, MapPos),
+    EventDestroyUnit(UnitId),
+    EventIndirectAttack(
+        UnitId,
+        /* weapon_index: */ MInt,
+        /* impact_pos: */ MapPos,
+        Vec<BlastHit>,
+    ),
+    EventCreateTileEffect(MapPos, TileEffectKind),
+    EventLayMines(UnitId, MapPos), // visible only to owner of the unit
+    EventClearMines(UnitId, MapPos),
+    EventRevealMinefield(MapPos, PlayerId), // visible only to this player
+    EventMineExplosion(UnitId, /* damage: */ MInt),
+    EventFortify(UnitId),
+    EventHealUnit(UnitId, UnitId, /* hit_points: */ MInt), // healer_id, unit_id
+    EventRestoreUnit(UnitId, /* hit_points: */ MInt), // rest or repair
+    EventRepairVehicle(UnitId, VehicleDamage), // one critical damage per turn
+    EventPlayerReady(PlayerId),
+    // Shows units that were deployed in secret to other players,
+    // veterans keep their experience and rank
+    EventRevealUnit(UnitId, MapPos, UnitTypeId, PlayerId,
+        /* experience: */ MInt, /* rank: */ MInt),
+    EventShowMessage(String),
+    EventChangeObjective(ObjectiveId, /* score: */ MInt, /* income: */ MInt),
+    EventGainExperience(UnitId, MInt), // veterans from previous battles
+    EventDraw,
+    EventOrdersDone(PlayerId), // simultaneous mode only
+    EventUndoMove(MoveUndoInfo),
+}
+
+// Move that continues at the start of every owner's turn
+// TODO: store in save file when saving of games is implemented
+#[deriving(Clone)]
+pub struct QueuedMove {
+    pub path: Vec<MapPos>, // starts at unit's current position
+    known_enemy_ids: Vec<UnitId>, // spotted enemies don't cancel the move
+}
+
+// State of the unit before its last move
+#[deriving(Clone)]
+pub struct MoveUndoInfo {
+    pub unit_id: UnitId,
+    pub path: Vec<MapPos>,
+    pub move_points: MInt,
+    pub is_fortified: bool,
+    pub fortification: MInt,
+    pub has_moved_or_fired: bool,
+    pub queued_move: Option<QueuedMove>, // replaced by this move
+}
+
+// Order that is resolved together with orders of other players
+#[deriving(Clone)]
+pub enum Order {
+    OrderMove(UnitId, Vec<MapPos>),
+    OrderAttack(UnitId, UnitId, WeaponChoice), // attacker_id, defender_id
+}
+
+#[deriving(Clone, PartialEq)]
+pub enum TurnMode {
+    Sequential, // players take turns one by one
+    Simultaneous, // all players give orders, then core resolves them
+}
+
+pub struct ScoreBreakdown {
+    pub objectives: MInt,
+    pub kills: MInt, // cost of destroyed enemy units
+    pub losses: MInt, // cost of own destroyed units
+}
+
+impl ScoreBreakdown {
+    pub fn total(&self) -> MInt {
+        self.objectives + self.kills - self.losses
+    }
+}
+
+// Unit that waits for deployment
+#[deriving(Clone)]
+pub struct RosterUnit {
+    pub type_id: UnitTypeId,
+    pub experience: MInt,
+}
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum GamePhase {
+    Deployment, // players place their rosters in deploy zones
+    Battle,
+}
+
+// Result of indirect attack for one unit in blast radius
+#[deriving(Clone)]
+pub struct BlastHit {
+    pub unit_id: UnitId,
+    pub damage: MInt,
 }
 
 pub struct Player {
     pub id: PlayerId,
 }
 
+pub enum VehicleDamage {
+    Immobilized,
+    WeaponDestroyed(/* weapon_index: */ MInt),
+}
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum TileEffectKind {
+    Smoke, // blocks line of sight
+    Wreck, // blocks line of sight and slows movement
+    Crater, // slows movement
+}
+
+impl TileEffectKind {
+    // Number of EventEndTurn before effect disappears
+    pub fn lifetime(&self) -> MInt {
+        match *self {
+            Smoke => 4,
+            Wreck => 6,
+            Crater => 20,
+        }
+    }
+
+    pub fn move_cost(&self) -> MInt {
+        match *self {
+            Smoke => 0,
+            Wreck => 2,
+            Crater => 1,
+        }
+    }
+
+    pub fn is_blocking_los(&self) -> bool {
+        match *self {
+            Smoke | Wreck => true,
+            Crater => false,
+        }
+    }
+}
+
+// Minefields are hidden from players that don't know about them
+#[deriving(Clone)]
+pub struct Minefield {
+    pub pos: MapPos,
+    pub known_by: Vec<PlayerId>,
+}
+
+#[deriving(Clone)]
+pub struct TileEffect {
+    pub pos: MapPos,
+    pub kind: TileEffectKind,
+    pub turns_left: MInt,
+}
+
 pub enum UnitClass {
     Infantry,
     Vehicle,
@@ -40,6 +230,11 @@ pub struct WeaponType {
     pub ap: MInt,
     pub accuracy: MInt,
     pub max_distance: MInt,
+    pub max_ammo: MInt,
+    pub suppression: MInt,
+    pub indirect: bool, // targets tile, not unit
+    pub blast_radius: MInt,
+    pub tile_effect: Option<TileEffectKind>, // created in impact tile
 }
 
 #[deriving(Clone)]
@@ -52,21 +247,98 @@ pub struct UnitType {
     pub size: MInt,
     pub armor: MInt,
     pub toughness: MInt,
+    pub hit_points: MInt,
     pub weapon_skill: MInt,
-    pub weapon_type_id: WeaponTypeId,
+    pub morale: MInt,
+    pub weapon_type_ids: Vec<WeaponTypeId>,
     pub move_points: MInt,
+    pub cost: MInt,
+    pub transport_capacity: MInt,
+    pub mines: MInt, // only engineers carry mines
+    pub medics: MInt, // squad members restored to adjacent infantry per turn
+    pub command_radius: MInt, // only leaders have it
+}
+
+impl UnitType {
+    // Engineers can lay and clear mines
+    pub fn is_engineer(&self) -> bool {
+        self.mines > 0
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.command_radius > 0
+    }
+
+    pub fn get_member_hit_points(&self) -> MInt {
+        max(1, self.hit_points / self.count)
+    }
 }
 
 #[deriving(Clone)]
 pub struct UnitTypeId{pub id: MInt}
 
+#[deriving(Clone, PartialEq, Show)]
+pub enum MoraleState {
+    Steady,
+    Pinned, // can not move
+    Routed, // can not attack and flees at turn start
+}
+
+// Modifiers that unit gets from friendly leaders
+#[deriving(Clone, PartialEq, Show)]
+pub struct CommandAura {
+    pub accuracy: MInt,
+    pub morale: MInt,
+    pub move_points: MInt,
+}
+
+// Units in command radius of friendly leader get bonuses,
+// all other units get penalties
+pub const IN_COMMAND_AURA: CommandAura = CommandAura {
+    accuracy: 1,
+    morale: 2,
+    move_points: 1,
+};
+pub const OUT_OF_COMMAND_AURA: CommandAura = CommandAura {
+    accuracy: -1,
+    morale: -1,
+    move_points: 0,
+};
+
+#[deriving(Clone)]
+pub struct Weapon {
+    pub type_id: WeaponTypeId,
+    pub attacked: bool,
+    pub ammo: MInt,
+    pub destroyed: bool,
+}
+
 pub struct Unit {
     pub id: UnitId,
     pub pos: MapPos,
     pub player_id: PlayerId,
     pub type_id: UnitTypeId,
     pub move_points: MInt,
-    pub attacked: bool,
+    pub hit_points: MInt,
+    pub immobilized: bool,
+    pub weapons: Vec<Weapon>,
+    pub suppression: MInt,
+    pub morale_state: MoraleState,
+    pub experience: MInt,
+    pub rank: MInt, // every rank gives +1 to weapon skill and morale
+    pub passenger_ids: Vec<UnitId>,
+    pub transporter_id: Option<UnitId>,
+    pub mines: MInt,
+    pub is_fortified: bool,
+    pub fortification: MInt, // cover bonus, grows every turn until unit moves
+    pub has_moved_or_fired: bool, // in this turn, only resting infantry recovers
+    pub aura: CommandAura, // updated by GameState after every event
+}
+
+impl Unit {
+    pub fn is_loaded(&self) -> bool {
+        self.transporter_id.is_some()
+    }
 }
 
 pub struct ObjectTypes {
@@ -93,6 +365,23 @@ impl ObjectTypes {
             ap: 9,
             accuracy: 5,
             max_distance: 5,
+            max_ammo: 20,
+            suppression: 4,
+            indirect: false,
+            blast_radius: 0,
+            tile_effect: None,
+        });
+        self.weapon_types.push(WeaponType {
+            name: "machine gun".to_string(),
+            damage: 2,
+            ap: 1,
+            accuracy: 4,
+            max_distance: 4,
+            max_ammo: 50,
+            suppression: 5,
+            indirect: false,
+            blast_radius: 0,
+            tile_effect: None,
         });
         self.weapon_types.push(WeaponType {
             name: "rifle".to_string(),
@@ -100,13 +389,71 @@ impl ObjectTypes {
             ap: 1,
             accuracy: 5,
             max_distance: 3,
+            max_ammo: 50,
+            suppression: 2,
+            indirect: false,
+            blast_radius: 0,
+            tile_effect: None,
+        });
+        self.weapon_types.push(WeaponType {
+            name: "launcher".to_string(),
+            damage: 7,
+            ap: 8,
+            accuracy: 3,
+            max_distance: 2,
+            max_ammo: 3,
+            suppression: 3,
+            indirect: false,
+            blast_radius: 0,
+            tile_effect: None,
+        });
+        self.weapon_types.push(WeaponType {
+            name: "mortar".to_string(),
+            damage: 5,
+            ap: 3,
+            accuracy: 2,
+            max_distance: 8,
+            max_ammo: 10,
+            suppression: 6,
+            indirect: true,
+            blast_radius: 1,
+            tile_effect: Some(Crater),
+        });
+        self.weapon_types.push(WeaponType {
+            name: "smoke shell".to_string(),
+            damage: 0,
+            ap: 0,
+            accuracy: 2,
+            max_distance: 8,
+            max_ammo: 3,
+            suppression: 0,
+            indirect: true,
+            blast_radius: 0,
+            tile_effect: Some(Smoke),
+        });
+        // Not carried by units, used for minefield explosions
+        self.weapon_types.push(WeaponType {
+            name: "mine".to_string(),
+            damage: 6,
+            ap: 6,
+            accuracy: 0,
+            max_distance: 0,
+            max_ammo: 0,
+            suppression: 4,
+            indirect: false,
+            blast_radius: 0,
+            tile_effect: None,
         });
     }
 
     // TODO: read from json/toml config
     fn get_unit_types(&mut self) {
         let cannon_id = self.get_weapon_type_id("cannon");
+        let machine_gun_id = self.get_weapon_type_id("machine gun");
         let rifle_id = self.get_weapon_type_id("rifle");
+        let launcher_id = self.get_weapon_type_id("launcher");
+        let mortar_id = self.get_weapon_type_id("mortar");
+        let smoke_shell_id = self.get_weapon_type_id("smoke shell");
         self.unit_types.push(UnitType {
             name: "tank".to_string(),
             class: Vehicle,
@@ -114,9 +461,16 @@ impl ObjectTypes {
             count: 1,
             armor: 11,
             toughness: 9,
+            hit_points: 3,
             weapon_skill: 5,
-            weapon_type_id: cannon_id,
+            morale: 8,
+            weapon_type_ids: vec![cannon_id, machine_gun_id],
             move_points: 5,
+            cost: 10,
+            transport_capacity: 0,
+            mines: 0,
+            medics: 0,
+            command_radius: 0,
         });
         self.unit_types.push(UnitType {
             name: "soldier".to_string(),
@@ -125,9 +479,106 @@ impl ObjectTypes {
             count: 4,
             armor: 1,
             toughness: 2,
+            hit_points: 4, // one for every soldier
+            weapon_skill: 5,
+            morale: 5,
+            weapon_type_ids: vec![rifle_id, launcher_id],
+            move_points: 3,
+            cost: 3,
+            transport_capacity: 0,
+            mines: 0,
+            medics: 0,
+            command_radius: 0,
+        });
+        self.unit_types.push(UnitType {
+            name: "truck".to_string(),
+            class: Vehicle,
+            size: 5,
+            count: 1,
+            armor: 2,
+            toughness: 4,
+            hit_points: 2,
+            weapon_skill: 0,
+            morale: 5,
+            weapon_type_ids: vec![],
+            move_points: 6,
+            cost: 4,
+            transport_capacity: 2,
+            mines: 0,
+            medics: 1, // supply truck
+            command_radius: 0,
+        });
+        self.unit_types.push(UnitType {
+            name: "mortar".to_string(),
+            class: Infantry,
+            size: 3,
+            count: 2,
+            armor: 1,
+            toughness: 2,
+            hit_points: 2,
+            weapon_skill: 4,
+            morale: 5,
+            weapon_type_ids: vec![mortar_id, smoke_shell_id],
+            move_points: 2,
+            cost: 5,
+            transport_capacity: 0,
+            mines: 0,
+            medics: 0,
+            command_radius: 0,
+        });
+        self.unit_types.push(UnitType {
+            name: "engineer".to_string(),
+            class: Infantry,
+            size: 4,
+            count: 3,
+            armor: 1,
+            toughness: 2,
+            hit_points: 3,
+            weapon_skill: 4,
+            morale: 5,
+            weapon_type_ids: vec![rifle_id],
+            move_points: 3,
+            cost: 4,
+            transport_capacity: 0,
+            mines: 2,
+            medics: 0,
+            command_radius: 0,
+        });
+        self.unit_types.push(UnitType {
+            name: "medic".to_string(),
+            class: Infantry,
+            size: 2,
+            count: 2,
+            armor: 1,
+            toughness: 2,
+            hit_points: 2,
+            weapon_skill: 0,
+            morale: 4,
+            weapon_type_ids: vec![],
+            move_points: 3,
+            cost: 3,
+            transport_capacity: 0,
+            mines: 0,
+            medics: 1,
+            command_radius: 0,
+        });
+        self.unit_types.push(UnitType {
+            name: "officer".to_string(),
+            class: Infantry,
+            size: 2,
+            count: 1,
+            armor: 1,
+            toughness: 2,
+            hit_points: 1,
             weapon_skill: 5,
-            weapon_type_id: rifle_id,
+            morale: 7,
+            weapon_type_ids: vec![rifle_id],
             move_points: 3,
+            cost: 6,
+            transport_capacity: 0,
+            mines: 0,
+            medics: 0,
+            command_radius: 3,
         });
     }
 
@@ -140,10 +591,22 @@ impl ObjectTypes {
         None
     }
 
+    pub fn get_unit_type_ids(&self) -> Vec<UnitTypeId> {
+        let mut ids = Vec::new();
+        for id in range(0, self.unit_types.len()) {
+            ids.push(UnitTypeId{id: id as MInt});
+        }
+        ids
+    }
+
     pub fn get_unit_type<'a>(&'a self, unit_type_id: UnitTypeId) -> &'a UnitType {
         &self.unit_types[unit_type_id.id as uint]
     }
 
+    pub fn get_weapon_type<'a>(&'a self, weapon_type_id: WeaponTypeId) -> &'a WeaponType {
+        &self.weapon_types[weapon_type_id.id as uint]
+    }
+
     fn get_unit_type_id(&self, name: &str) -> UnitTypeId {
         match self.get_unit_type_id_opt(name) {
             Some(id) => id,
@@ -161,6 +624,170 @@ impl ObjectTypes {
     }
 }
 
+const BAIL_OUT_SUPPRESSION: MInt = 5;
+
+// Friendly unit sees all tiles in this distance
+// if line of sight is not blocked
+const SPOTTING_DISTANCE: MInt = 4;
+
+// Experience needed for every rank: regular, veteran, elite
+const RANK_EXPERIENCE: [MInt, ..3] = [3, 8, 15];
+
+pub fn get_rank(experience: MInt) -> MInt {
+    let mut rank = 0;
+    for needed_experience in RANK_EXPERIENCE.iter() {
+        if experience >= *needed_experience {
+            rank += 1;
+        }
+    }
+    rank
+}
+
+fn get_critical_damage(unit: &Unit) -> Option<VehicleDamage> {
+    if unit.immobilized {
+        return Some(Immobilized);
+    }
+    unit.weapons.iter().position(|weapon| weapon.destroyed)
+        .map(|index| WeaponDestroyed(index as MInt))
+}
+
+pub fn is_weapon_usable(
+    object_types: &ObjectTypes,
+    attacker: &Unit,
+    defender: &Unit,
+    weapon_index: MInt,
+) -> bool {
+    if weapon_index < 0 || weapon_index as uint >= attacker.weapons.len() {
+        return false;
+    }
+    if attacker.morale_state == Routed || defender.is_loaded() {
+        return false;
+    }
+    let weapon = &attacker.weapons[weapon_index as uint];
+    let weapon_type = object_types.get_weapon_type(weapon.type_id);
+    !weapon_type.indirect
+        && !weapon.attacked && !weapon.destroyed && weapon.ammo > 0
+        && distance(attacker.pos, defender.pos) <= weapon_type.max_distance
+}
+
+pub fn is_indirect_weapon_usable(
+    object_types: &ObjectTypes,
+    unit: &Unit,
+    weapon_index: MInt,
+) -> bool {
+    if weapon_index < 0 || weapon_index as uint >= unit.weapons.len() {
+        return false;
+    }
+    if unit.morale_state == Routed || unit.is_loaded() {
+        return false;
+    }
+    let weapon = &unit.weapons[weapon_index as uint];
+    let weapon_type = object_types.get_weapon_type(weapon.type_id);
+    weapon_type.indirect
+        && !weapon.attacked && !weapon.destroyed && weapon.ammo > 0
+}
+
+// Returns index of first indirect weapon that can fire this turn
+pub fn get_indirect_weapon_index(
+    object_types: &ObjectTypes,
+    unit: &Unit,
+    is_smoke: bool,
+) -> Option<MInt> {
+    for (i, weapon) in unit.weapons.iter().enumerate() {
+        let weapon_type = object_types.get_weapon_type(weapon.type_id);
+        if is_indirect_weapon_usable(object_types, unit, i as MInt)
+            && (weapon_type.tile_effect == Some(Smoke)) == is_smoke
+        {
+            return Some(i as MInt);
+        }
+    }
+    None
+}
+
+// Returns (hit_test_v, pierce_test_v, wound_test_v)
+fn get_test_values(
+    rules: &Rules,
+    object_types: &ObjectTypes,
+    attacker: &Unit,
+    defender: &Unit,
+    weapon_index: MInt,
+) -> (MInt, MInt, MInt) {
+    let weapon = &attacker.weapons[weapon_index as uint];
+    let weapon_type = object_types.get_weapon_type(weapon.type_id);
+    let attacker_type = object_types.get_unit_type(attacker.type_id);
+    let defender_type = object_types.get_unit_type(defender.type_id);
+    let hit_test_v = rules.hit_base + defender_type.size
+        + weapon_type.accuracy + attacker_type.weapon_skill + attacker.rank
+        + attacker.aura.accuracy - attacker.suppression / 2 - defender.fortification;
+    let pierce_test_v = rules.pierce_base - defender_type.armor + weapon_type.ap;
+    let wound_test_v = rules.wound_base
+        - defender_type.toughness + weapon_type.damage;
+    (hit_test_v, pierce_test_v, wound_test_v)
+}
+
+fn get_max_damage(weapon_type: &WeaponType, defender_type: &UnitType) -> MInt {
+    max(1, weapon_type.damage - max(0, defender_type.armor - weapon_type.ap))
+}
+
+// Probabilities of passing each step of Core::hit_test.
+// Every value includes all previous steps.
+#[deriving(Clone, Show)]
+pub struct CombatOdds {
+    pub hit: f32,
+    pub pierce: f32,
+    pub wound: f32,
+    pub kill: f32,
+}
+
+pub fn get_combat_odds(
+    rules: &Rules,
+    object_types: &ObjectTypes,
+    attacker: &Unit,
+    defender: &Unit,
+    weapon_index: MInt,
+) -> CombatOdds {
+    let weapon = &attacker.weapons[weapon_index as uint];
+    let weapon_type = object_types.get_weapon_type(weapon.type_id);
+    if distance(attacker.pos, defender.pos) > weapon_type.max_distance {
+        return CombatOdds{hit: 0.0, pierce: 0.0, wound: 0.0, kill: 0.0};
+    }
+    let defender_type = object_types.get_unit_type(defender.type_id);
+    let (hit_test_v, pierce_test_v, wound_test_v) = get_test_values(
+        rules, object_types, attacker, defender, weapon_index);
+    let hit = rules.test_probability(hit_test_v);
+    let pierce = hit * rules.test_probability(pierce_test_v);
+    let wound = pierce * rules.test_probability(wound_test_v);
+    // damage is uniformly distributed in [1, max_damage]
+    let max_damage = get_max_damage(weapon_type, defender_type);
+    let killing_damages = max(0, max_damage - defender.hit_points + 1);
+    let kill = wound * killing_damages as f32 / max_damage as f32;
+    CombatOdds{hit: hit, pierce: pierce, wound: wound, kill: kill}
+}
+
+pub fn get_best_weapon_index(
+    rules: &Rules,
+    object_types: &ObjectTypes,
+    attacker: &Unit,
+    defender: &Unit,
+) -> Option<MInt> {
+    let mut best_index = None;
+    let mut best_odds = CombatOdds{hit: 0.0, pierce: 0.0, wound: 0.0, kill: 0.0};
+    for i in range(0, attacker.weapons.len() as MInt) {
+        if !is_weapon_usable(object_types, attacker, defender, i) {
+            continue;
+        }
+        let odds = get_combat_odds(
+            rules, object_types, attacker, defender, i);
+        let is_better = odds.kill > best_odds.kill
+            || (odds.kill == best_odds.kill && odds.wound > best_odds.wound);
+        if best_index.is_none() || is_better {
+            best_index = Some(i);
+            best_odds = odds;
+        }
+    }
+    best_index
+}
+
 pub struct Core {
     game_state: GameState,
     players: Vec<Player>,
@@ -169,6 +796,24 @@ pub struct Core {
     event_lists: HashMap<PlayerId, Vec<Event>>,
     map_size: Size2<MInt>,
     object_types: ObjectTypes,
+    scenario: Scenario,
+    rules: Rules,
+    phase: GamePhase,
+    turn: MInt, // increases every time some player ends turn
+    round: MInt, // increases when first player starts new round
+    losses: Vec<(PlayerId, UnitTypeId)>, // all destroyed units
+    fired_trigger_ids: Vec<uint>,
+    rosters: HashMap<PlayerId, Vec<RosterUnit>>, // units left to deploy
+    ready_player_ids: Vec<PlayerId>,
+    winner_id: Option<PlayerId>,
+    is_game_over: bool,
+    turn_mode: TurnMode,
+    orders: HashMap<PlayerId, Vec<Order>>,
+    orders_done_player_ids: Vec<PlayerId>,
+    last_move: Option<MoveUndoInfo>,
+    next_unit_id: MInt, // ids are not reused even if unit is not created yet
+    queued_moves: HashMap<UnitId, QueuedMove>,
+    pathfinder: Pathfinder,
 }
 
 fn get_event_lists() -> HashMap<PlayerId, Vec<Event>> {
@@ -185,11 +830,31 @@ fn get_players_list() -> Vec<Player> {
     )
 }
 
+fn get_empty_orders() -> HashMap<PlayerId, Vec<Order>> {
+    let mut orders = HashMap::new();
+    for player in get_players_list().iter() {
+        orders.insert(player.id, Vec::new());
+    }
+    orders
+}
+
 impl Core {
     pub fn new(fs: &FileSystem) -> Core {
-        set_error_context!("constructing Core", "-");
+        Core::new_with_scenario(fs, "skirmish")
+    }
+
+    pub fn new_with_scenario(fs: &FileSystem, scenario_name: &str) -> Core {
+        set_error_context!("constructing Core", scenario_name);
         let config = Config::new(&fs.get(&Path::new("data/conf_core.json")));
         let map_size = config.get("map_size");
+        let scenario = Scenario::from_name(scenario_name, map_size);
+        let rules = match scenario.rules {
+            Some(ref rules) => {
+                rules.check();
+                rules.clone()
+            },
+            None => Rules::from_config(&config),
+        };
         let mut core = Core {
             game_state: GameState::new(),
             players: get_players_list(),
@@ -198,8 +863,28 @@ impl Core {
             event_lists: get_event_lists(),
             map_size: map_size,
             object_types: ObjectTypes::new(),
+            scenario: scenario,
+            rules: rules,
+            phase: Deployment,
+            turn: 1,
+            round: 1,
+            losses: Vec::new(),
+            fired_trigger_ids: Vec::new(),
+            rosters: HashMap::new(),
+            ready_player_ids: Vec::new(),
+            winner_id: None,
+            is_game_over: false,
+            turn_mode: Sequential,
+            orders: get_empty_orders(),
+            orders_done_player_ids: Vec::new(),
+            last_move: None,
+            next_unit_id: 0,
+            queued_moves: HashMap::new(),
+            pathfinder: Pathfinder::new(map_size),
         };
-        core.get_units();
+        core.get_rosters();
+        core.get_minefields();
+        core.get_start_resources();
         core
     }
 
@@ -207,183 +892,1846 @@ impl Core {
         &self.object_types
     }
 
-    // TODO: Move to scenario.json
-    fn get_units(&mut self) {
-        let tank_id = self.object_types.get_unit_type_id("tank");
-        let soldier_id = self.object_types.get_unit_type_id("soldier");
-        self.add_unit(MapPos{v: Vector2{x: 0, y: 0}}, tank_id, PlayerId{id: 0});
-        self.add_unit(MapPos{v: Vector2{x: 0, y: 1}}, soldier_id, PlayerId{id: 0});
-        self.add_unit(MapPos{v: Vector2{x: 2, y: 0}}, tank_id, PlayerId{id: 1});
-        self.add_unit(MapPos{v: Vector2{x: 2, y: 2}}, soldier_id, PlayerId{id: 1});
+    pub fn scenario(&self) -> &Scenario {
+        &self.scenario
     }
 
-    fn get_new_unit_id(&self) -> UnitId {
-        let id = match self.game_state.units.keys().max_by(|&n| n) {
-            Some(n) => n.id + 1,
-            None => 0,
-        };
-        UnitId{id: id}
+    pub fn rules(&self) -> &Rules {
+        &self.rules
     }
 
-    fn add_unit(&mut self, pos: MapPos, type_id: UnitTypeId, player_id: PlayerId) {
-        let event = EventCreateUnit(
-            self.get_new_unit_id(),
-            pos,
-            type_id,
-            player_id,
-        );
-        self.do_core_event(event);
+    fn get_start_resources(&mut self) {
+        let start_resources = self.scenario.start_resources;
+        let mut events = Vec::new();
+        for player in self.players.iter() {
+            events.push(EventChangeResources(player.id, start_resources));
+        }
+        for event in events.into_iter() {
+            self.do_core_event(event);
+        }
     }
 
-    pub fn map_size(&self) -> Size2<MInt> {
-        self.map_size
+    fn get_income(&self, player_id: PlayerId) -> MInt {
+        let mut income = self.scenario.income;
+        for id in self.scenario.get_objective_ids().into_iter() {
+            if self.game_state.get_objective_owner(id) == Some(player_id) {
+                income += self.scenario.get_objective(id).income;
+            }
+        }
+        income
     }
 
-    fn get_unit<'a>(&'a self, id: UnitId) -> &'a Unit {
-        match self.game_state.units.find(&id) {
-            Some(unit) => unit,
-            None => panic!("No unit with id = {}", id.id),
+    fn get_objectives_score(&self, player_id: PlayerId) -> MInt {
+        let mut score = 0;
+        for id in self.scenario.get_objective_ids().into_iter() {
+            if self.game_state.get_objective_owner(id) == Some(player_id) {
+                score += self.scenario.get_objective(id).score;
+            }
         }
+        score
     }
 
-    pub fn get_weapon_type(&self, weapon_type_id: WeaponTypeId) -> &WeaponType {
-        &self.object_types.weapon_types[weapon_type_id.id as uint]
+    // Objective is contested if there are enemy units nearby
+    fn is_objective_contested(&self, id: ObjectiveId, player_id: PlayerId) -> bool {
+        let pos = self.scenario.get_objective(id).pos;
+        for (_, unit) in self.game_state.units.iter() {
+            if unit.player_id != player_id && distance(unit.pos, pos) <= 1 {
+                return true;
+            }
+        }
+        false
     }
 
-    fn hit_test(&self, attacker_id: UnitId, defender_id: UnitId) -> bool {
-        fn test(needed: MInt) -> bool {
-            let real = task_rng().gen_range(-5i32, 5i32);
-            let result = real < needed;
-            println!("real:{} < needed:{} = {}", real, needed, result);
-            result
-        }
-        println!("");
-        let attacker = self.get_unit(attacker_id);
-        let defender = self.get_unit(defender_id);
-        let attacker_type = self.object_types.get_unit_type(attacker.type_id);
-        let defender_type = self.object_types.get_unit_type(defender.type_id);
-        let weapon_type = self.get_weapon_type(attacker_type.weapon_type_id);
-        if distance(attacker.pos, defender.pos) > weapon_type.max_distance {
-            return false;
-        }
-        let hit_test_v = -15 + defender_type.size
-            + weapon_type.accuracy + attacker_type.weapon_skill;
-        let pierce_test_v = 5 + -defender_type.armor + weapon_type.ap;
-        let wound_test_v = -defender_type.toughness + weapon_type.damage;
-        println!("hit_test = {}, pierce_test = {}, wound_test_v = {}",
-            hit_test_v, pierce_test_v, wound_test_v);
-        print!("hit test: ");
-        if !test(hit_test_v) {
-            return false;
-        }
-        print!("pierce test: ");
-        if !test(pierce_test_v) {
-            return false;
+    fn get_capture_objective_events(&self, player_id: PlayerId) -> Vec<Event> {
+        let mut events = Vec::new();
+        for id in self.scenario.get_objective_ids().into_iter() {
+            if self.game_state.get_objective_owner(id) == Some(player_id) {
+                continue;
+            }
+            let pos = self.scenario.get_objective(id).pos;
+            let is_occupied = self.game_state.units_at(pos).iter()
+                .any(|unit| unit.player_id == player_id);
+            if is_occupied && !self.is_objective_contested(id, player_id) {
+                events.push(EventCaptureObjective(id, player_id));
+            }
         }
-        print!("wound test: ");
-        if !test(wound_test_v) {
-            return false;
+        events
+    }
+
+    fn get_rosters(&mut self) {
+        for player in self.players.iter() {
+            let mut roster = Vec::new();
+            match self.scenario.rosters.find(&player.id) {
+                Some(names) => {
+                    for name in names.iter() {
+                        roster.push(RosterUnit {
+                            type_id: self.object_types.get_unit_type_id(
+                                name.as_slice()),
+                            experience: 0,
+                        });
+                    }
+                },
+                None => {},
+            }
+            self.rosters.insert(player.id, roster);
         }
-        println!("HIT!");
-        true
     }
 
-    pub fn player_id(&self) -> PlayerId {
-        self.current_player_id
+    pub fn phase(&self) -> GamePhase {
+        self.phase
     }
 
-    pub fn get_event(&mut self) -> Option<Event> {
-        let list = self.event_lists.get_mut(&self.current_player_id);
-        list.remove(0)
+    pub fn turn(&self) -> MInt {
+        self.turn
     }
 
-    fn command_attack_unit_to_event(
-        &self,
-        attacker_id: UnitId,
-        defender_id: UnitId
-    ) -> Option<Event> {
-        let attacker = &self.game_state.units[attacker_id];
-        let defender = &self.game_state.units[defender_id];
-        let attacker_type = self.object_types.get_unit_type(attacker.type_id);
-        let weapon_type = self.get_weapon_type(attacker_type.weapon_type_id);
-        if distance(attacker.pos, defender.pos) <= weapon_type.max_distance {
-            Some(EventAttackUnit(
-                attacker_id,
-                defender_id,
-                self.hit_test(attacker_id, defender_id),
-            ))
-        } else {
-            None
-        }
+    pub fn round(&self) -> MInt {
+        self.round
     }
 
-    fn command_to_event(&self, command: Command) -> Option<Event> {
-        match command {
-            CommandEndTurn => {
-                let old_id = self.current_player_id.id;
-                let max_id = self.players.len() as MInt;
-                let new_id = if old_id + 1 == max_id {
-                    0
-                } else {
-                    old_id + 1
-                };
-                Some(EventEndTurn(PlayerId{id: old_id}, PlayerId{id: new_id}))
-            },
-            CommandCreateUnit(pos) => {
-                Some(EventCreateUnit(
-                    self.get_new_unit_id(),
-                    pos,
-                    self.object_types.get_unit_type_id("soldier"),
-                    self.current_player_id,
-                ))
-            },
-            CommandMove(unit_id, path) => {
-                Some(EventMove(unit_id, path))
-            },
-            CommandAttackUnit(attacker_id, defender_id) => {
-                self.command_attack_unit_to_event(attacker_id, defender_id)
-            },
-        }
+    pub fn is_game_over(&self) -> bool {
+        self.is_game_over
     }
 
-    pub fn do_command(&mut self, command: Command) {
-        match self.command_to_event(command) {
-            Some(event) => self.do_core_event(event),
-            None => {},
-        }
+    pub fn turn_mode(&self) -> TurnMode {
+        self.turn_mode.clone()
     }
 
-    fn do_core_event(&mut self, core_event: Event) {
-        self.core_event_list.push(core_event);
-        self.make_events();
+    // Mode can be changed only before the battle
+    pub fn set_turn_mode(&mut self, turn_mode: TurnMode) {
+        assert!(self.phase == Deployment);
+        self.turn_mode = turn_mode;
     }
 
-    fn apply_event(&mut self, event: &Event) {
-        match *event {
-            EventEndTurn(old_player_id, new_player_id) => {
-                for player in self.players.iter() {
-                    if player.id == new_player_id {
-                        if self.current_player_id == old_player_id {
-                            self.current_player_id = player.id;
-                        }
-                        return;
-                    }
-                }
-            },
-            _ => {},
+    pub fn get_queued_move(&self, unit_id: UnitId) -> Option<&QueuedMove> {
+        self.queued_moves.find(&unit_id)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.last_move.is_some()
+    }
+
+    // Not yet resolved orders of the player
+    pub fn get_orders(&self, player_id: PlayerId) -> &Vec<Order> {
+        &self.orders[player_id]
+    }
+
+    fn get_losses_cost(&self, player_id: PlayerId) -> MInt {
+        let mut cost = 0;
+        for &(id, type_id) in self.losses.iter() {
+            if id == player_id {
+                cost += self.object_types.get_unit_type(type_id).cost;
+            }
         }
+        cost
     }
 
-    fn make_events(&mut self) {
-        while self.core_event_list.len() != 0 {
-            let event = self.core_event_list.pop().unwrap();
-            self.apply_event(&event);
+    pub fn get_score_breakdown(&self, player_id: PlayerId) -> ScoreBreakdown {
+        let mut kills = 0;
+        for player in self.players.iter() {
+            if player.id != player_id {
+                kills += self.get_losses_cost(player.id);
+            }
+        }
+        ScoreBreakdown {
+            objectives: self.game_state.get_score(player_id),
+            kills: kills,
+            losses: self.get_losses_cost(player_id),
+        }
+    }
+
+    // Player with best total score wins, equal scores are a draw
+    fn get_round_limit_event(&self) -> Event {
+        let mut best_id = None;
+        let mut best_score = 0;
+        let mut is_draw = false;
+        for player in self.players.iter() {
+            let score = self.get_score_breakdown(player.id).total();
+            if best_id.is_none() || score > best_score {
+                best_id = Some(player.id);
+                best_score = score;
+                is_draw = false;
+            } else if score == best_score {
+                is_draw = true;
+            }
+        }
+        if is_draw {
+            EventDraw
+        } else {
+            EventVictory(best_id.unwrap())
+        }
+    }
+
+    // Units that player still has to deploy
+    pub fn get_roster(&self, player_id: PlayerId) -> &Vec<RosterUnit> {
+        &self.rosters[player_id]
+    }
+
+    // Replaces scenario roster with units from previous battle
+    pub fn import_units(&mut self, player_id: PlayerId, units: &Vec<CampaignUnit>) {
+        assert!(self.phase == Deployment);
+        let mut roster = Vec::new();
+        for unit in units.iter() {
+            roster.push(RosterUnit {
+                type_id: self.object_types.get_unit_type_id(
+                    unit.type_name.as_slice()),
+                experience: unit.experience,
+            });
+        }
+        self.rosters.insert(player_id, roster);
+    }
+
+    // Surviving units of the player
+    pub fn export_units(&self, player_id: PlayerId) -> Vec<CampaignUnit> {
+        let mut units = Vec::new();
+        for unit in self.game_state.units.values() {
+            if unit.player_id != player_id {
+                continue;
+            }
+            let unit_type = self.object_types.get_unit_type(unit.type_id);
+            units.push(CampaignUnit {
+                type_name: unit_type.name.clone(),
+                experience: unit.experience,
+            });
+        }
+        units
+    }
+
+    pub fn import_resources(&mut self, player_id: PlayerId, resources: MInt) {
+        let diff = resources - self.game_state.get_resources(player_id);
+        self.do_core_event(EventChangeResources(player_id, diff));
+    }
+
+    pub fn export_resources(&self, player_id: PlayerId) -> MInt {
+        self.game_state.get_resources(player_id)
+    }
+
+    pub fn winner_id(&self) -> Option<PlayerId> {
+        self.winner_id
+    }
+
+    fn get_minefields(&mut self) {
+        let mut events = Vec::new();
+        for minefield in self.scenario.minefields.iter() {
+            events.push(EventRevealMinefield(minefield.pos, minefield.player_id));
+        }
+        for event in events.into_iter() {
+            self.do_core_event(event);
+        }
+    }
+
+    fn get_new_unit_id(&mut self) -> UnitId {
+        let id = self.next_unit_id;
+        self.next_unit_id += 1;
+        UnitId{id: id}
+    }
+
+    pub fn map_size(&self) -> Size2<MInt> {
+        self.map_size
+    }
+
+    fn get_unit<'a>(&'a self, id: UnitId) -> &'a Unit {
+        match self.game_state.units.find(&id) {
+            Some(unit) => unit,
+            None => panic!("No unit with id = {}", id.id),
+        }
+    }
+
+    pub fn get_weapon_type(&self, weapon_type_id: WeaponTypeId) -> &WeaponType {
+        self.object_types.get_weapon_type(weapon_type_id)
+    }
+
+    // Returns damage
+    fn hit_test(
+        &self,
+        attacker_id: UnitId,
+        defender_id: UnitId,
+        weapon_index: MInt,
+    ) -> MInt {
+        let attacker = self.get_unit(attacker_id);
+        let defender = self.get_unit(defender_id);
+        let defender_type = self.object_types.get_unit_type(defender.type_id);
+        let weapon = &attacker.weapons[weapon_index as uint];
+        let weapon_type = self.get_weapon_type(weapon.type_id);
+        if distance(attacker.pos, defender.pos) > weapon_type.max_distance {
+            return 0;
+        }
+        let (hit_test_v, pierce_test_v, wound_test_v) = get_test_values(
+            &self.rules, &self.object_types, attacker, defender, weapon_index);
+        if !self.rules.test(hit_test_v) {
+            return 0;
+        }
+        self.damage_test(weapon_type, defender_type, pierce_test_v, wound_test_v)
+    }
+
+    // Pierce and wound tests of unit that was already hit
+    fn damage_test(
+        &self,
+        weapon_type: &WeaponType,
+        defender_type: &UnitType,
+        pierce_test_v: MInt,
+        wound_test_v: MInt,
+    ) -> MInt {
+        if !self.rules.test(pierce_test_v) {
+            return 0;
+        }
+        if !self.rules.test(wound_test_v) {
+            return 0;
+        }
+        let max_damage = get_max_damage(weapon_type, defender_type);
+        task_rng().gen_range(1, max_damage + 1)
+    }
+
+    // Blast hits every unit in the tile, so there is no hit test
+    fn blast_test(
+        &self,
+        attacker_id: UnitId,
+        defender_id: UnitId,
+        weapon_index: MInt,
+    ) -> MInt {
+        let attacker = self.get_unit(attacker_id);
+        let defender = self.get_unit(defender_id);
+        let defender_type = self.object_types.get_unit_type(defender.type_id);
+        let weapon = &attacker.weapons[weapon_index as uint];
+        let weapon_type = self.get_weapon_type(weapon.type_id);
+        let (_, pierce_test_v, wound_test_v) = get_test_values(
+            &self.rules, &self.object_types, attacker, defender, weapon_index);
+        self.damage_test(weapon_type, defender_type, pierce_test_v, wound_test_v)
+    }
+
+    // Damaged vehicle that survived can be immobilized or lose one weapon
+    fn get_damage_vehicle_event(&self, unit_id: UnitId) -> Option<Event> {
+        let unit = self.get_unit(unit_id);
+        match self.object_types.get_unit_type(unit.type_id).class {
+            Vehicle => {},
+            Infantry => return None,
+        }
+        if self.rules.test(0) {
+            return None;
+        }
+        let mut weapon_indexes = Vec::new();
+        for (i, weapon) in unit.weapons.iter().enumerate() {
+            if !weapon.destroyed {
+                weapon_indexes.push(i as MInt);
+            }
+        }
+        let mut rng = task_rng();
+        let damage = if !unit.immobilized
+            && (weapon_indexes.len() == 0 || rng.gen::<bool>())
+        {
+            Immobilized
+        } else {
+            match rng.choose(weapon_indexes.as_slice()) {
+                Some(weapon_index) => WeaponDestroyed(*weapon_index),
+                None => return None,
+            }
+        };
+        Some(EventDamageVehicle(unit_id, damage))
+    }
+
+    // Suppression is accumulated by infantry from all incoming fire,
+    // failed morale test makes steady unit pinned and pinned unit routed
+    fn get_suppress_event(
+        &self,
+        attacker_id: UnitId,
+        defender_id: UnitId,
+        weapon_index: MInt,
+    ) -> Option<Event> {
+        let attacker = self.get_unit(attacker_id);
+        let defender = self.get_unit(defender_id);
+        let defender_type = self.object_types.get_unit_type(defender.type_id);
+        match defender_type.class {
+            Infantry => {},
+            Vehicle => return None,
+        }
+        let weapon = &attacker.weapons[weapon_index as uint];
+        let suppression = self.get_weapon_type(weapon.type_id).suppression;
+        let morale_test_v = defender_type.morale + defender.rank
+            + defender.aura.morale - (defender.suppression + suppression);
+        let morale_state = if self.rules.test(morale_test_v) {
+            defender.morale_state
+        } else {
+            match defender.morale_state {
+                Steady => Pinned,
+                Pinned | Routed => Routed,
+            }
+        };
+        Some(EventSuppressUnit(defender_id, suppression, morale_state))
+    }
+
+    pub fn player_id(&self) -> PlayerId {
+        self.current_player_id
+    }
+
+    pub fn get_event(&mut self) -> Option<Event> {
+        let list = self.event_lists.get_mut(&self.current_player_id);
+        list.remove(0)
+    }
+
+    fn command_attack_unit_to_events(
+        &self,
+        attacker_id: UnitId,
+        defender_id: UnitId,
+        weapon_choice: WeaponChoice,
+    ) -> Vec<Event> {
+        let attacker = &self.game_state.units[attacker_id];
+        let defender = &self.game_state.units[defender_id];
+        if attacker.player_id != self.current_player_id
+            || defender.player_id == attacker.player_id
+            || !is_los_clear(&self.game_state, attacker.pos, defender.pos)
+        {
+            return vec![];
+        }
+        let weapon_index = match weapon_choice {
+            BestWeapon => {
+                match get_best_weapon_index(
+                    &self.rules, &self.object_types, attacker, defender)
+                {
+                    Some(weapon_index) => weapon_index,
+                    None => return vec![],
+                }
+            },
+            WeaponIndex(weapon_index) => {
+                if !is_weapon_usable(
+                    &self.object_types, attacker, defender, weapon_index)
+                {
+                    return vec![];
+                }
+                weapon_index
+            },
+        };
+        let damage = self.hit_test(attacker_id, defender_id, weapon_index);
+        let mut events = vec![EventAttackUnit(
+            attacker_id,
+            defender_id,
+            weapon_index,
+            damage,
+        )];
+        if damage >= defender.hit_points {
+            match self.get_wreck_event(defender_id) {
+                Some(event) => events.push(event),
+                None => {},
+            }
+        } else {
+            match self.get_suppress_event(
+                attacker_id, defender_id, weapon_index)
+            {
+                Some(event) => events.push(event),
+                None => {},
+            }
+            if damage > 0 {
+                match self.get_damage_vehicle_event(defender_id) {
+                    Some(event) => events.push(event),
+                    None => {},
+                }
+            }
+        }
+        events
+    }
+
+    pub fn is_pos_spotted(&self, player_id: PlayerId, pos: MapPos) -> bool {
+        for (_, unit) in self.game_state.units.iter() {
+            if unit.player_id == player_id && !unit.is_loaded()
+                && distance(unit.pos, pos) <= SPOTTING_DISTANCE
+                && is_los_clear(&self.game_state, unit.pos, pos)
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    // Shell that failed accuracy test lands in random neighbour tile
+    fn get_impact_pos(&self, target_pos: MapPos, accuracy: MInt) -> MapPos {
+        if self.rules.test(accuracy) {
+            return target_pos;
+        }
+        let mut positions = Vec::new();
+        for pos in MapPosIter::new(self.map_size) {
+            if distance(target_pos, pos) == 1 {
+                positions.push(pos);
+            }
+        }
+        match task_rng().choose(positions.as_slice()) {
+            Some(pos) => *pos,
+            None => target_pos,
+        }
+    }
+
+    fn command_attack_pos_to_events(
+        &self,
+        attacker_id: UnitId,
+        target_pos: MapPos,
+        weapon_index: MInt,
+    ) -> Vec<Event> {
+        let attacker = self.get_unit(attacker_id);
+        if !is_indirect_weapon_usable(&self.object_types, attacker, weapon_index) {
+            return vec![];
+        }
+        let weapon = &attacker.weapons[weapon_index as uint];
+        let weapon_type = self.get_weapon_type(weapon.type_id);
+        if attacker.player_id != self.current_player_id
+            || !self.is_inboard(target_pos)
+            || distance(attacker.pos, target_pos) > weapon_type.max_distance
+            || !self.is_pos_spotted(attacker.player_id, target_pos)
+        {
+            return vec![];
+        }
+        let attacker_type = self.object_types.get_unit_type(attacker.type_id);
+        let accuracy = self.rules.scatter_base + weapon_type.accuracy
+            + attacker_type.weapon_skill + attacker.rank
+            - attacker.suppression / 2;
+        let impact_pos = self.get_impact_pos(target_pos, accuracy);
+        let mut hits = Vec::new();
+        let mut events = Vec::new();
+        for (id, unit) in self.game_state.units.iter() {
+            if unit.is_loaded() || weapon_type.damage == 0
+                || distance(unit.pos, impact_pos) > weapon_type.blast_radius
+            {
+                continue;
+            }
+            let damage = self.blast_test(attacker_id, *id, weapon_index);
+            hits.push(BlastHit{unit_id: *id, damage: damage});
+            if damage >= unit.hit_points {
+                match self.get_wreck_event(*id) {
+                    Some(event) => events.push(event),
+                    None => {},
+                }
+            } else {
+                match self.get_suppress_event(attacker_id, *id, weapon_index) {
+                    Some(event) => events.push(event),
+                    None => {},
+                }
+                if damage > 0 {
+                    match self.get_damage_vehicle_event(*id) {
+                        Some(event) => events.push(event),
+                        None => {},
+                    }
+                }
+            }
+        }
+        match weapon_type.tile_effect {
+            Some(kind) => events.push(EventCreateTileEffect(impact_pos, kind)),
+            None => {},
+        }
+        events.insert(0, EventIndirectAttack(
+            attacker_id, weapon_index, impact_pos, hits));
+        events
+    }
+
+    // Destroyed vehicles leave burning wrecks
+    fn get_wreck_event(&self, unit_id: UnitId) -> Option<Event> {
+        let unit = self.get_unit(unit_id);
+        match self.object_types.get_unit_type(unit.type_id).class {
+            Vehicle => Some(EventCreateTileEffect(unit.pos, Wreck)),
+            Infantry => None,
+        }
+    }
+
+    fn command_buy_unit_to_events(
+        &mut self,
+        type_id: UnitTypeId,
+        pos: MapPos,
+    ) -> Vec<Event> {
+        let player_id = self.current_player_id;
+        let cost = self.object_types.get_unit_type(type_id).cost;
+        if !self.scenario.is_deploy_pos(player_id, pos)
+            || !self.can_stack(pos, player_id, type_id)
+            || self.game_state.get_resources(player_id) < cost
+        {
+            return vec![];
+        }
+        let unit_id = self.get_new_unit_id();
+        vec![
+            EventChangeResources(player_id, -cost),
+            EventCreateUnit(unit_id, pos, type_id, player_id),
+        ]
+    }
+
+    fn can_stack(
+        &self,
+        pos: MapPos,
+        player_id: PlayerId,
+        type_id: UnitTypeId,
+    ) -> bool {
+        self.game_state.can_stack(
+            &self.object_types,
+            self.scenario.stack_limit,
+            pos,
+            player_id,
+            type_id,
+        )
+    }
+
+    fn command_move_to_events(
+        &self,
+        unit_id: UnitId,
+        path: Vec<MapPos>,
+    ) -> Vec<Event> {
+        let unit = self.get_unit(unit_id);
+        if path.len() < 2 || unit.move_points == 0 || unit.is_loaded()
+            || !self.can_stack(*path.last().unwrap(), unit.player_id, unit.type_id)
+        {
+            return vec![];
+        }
+        let mut cost = 0;
+        for pos in path.slice_from(1).iter() {
+            cost += self.game_state.get_move_cost(*pos);
+        }
+        if cost > unit.move_points {
+            return vec![];
+        }
+        self.get_move_events(unit_id, path)
+    }
+
+    // Unit that enters minefield stops there and triggers explosion
+    fn get_move_events(&self, unit_id: UnitId, path: Vec<MapPos>) -> Vec<Event> {
+        let mined_pos = path.slice_from(1).iter()
+            .find(|pos| self.game_state.get_minefield(**pos).is_some())
+            .map(|pos| *pos);
+        let mined_pos = match mined_pos {
+            Some(pos) => pos,
+            None => return vec![EventMove(unit_id, path)],
+        };
+        let index = path.iter().position(|pos| *pos == mined_pos).unwrap();
+        let mut events = vec![EventMove(unit_id, path.slice_to(index + 1).to_vec())];
+        events.push_all_move(self.get_mine_explosion_events(unit_id, mined_pos));
+        events
+    }
+
+    fn get_mine_explosion_events(&self, unit_id: UnitId, mined_pos: MapPos) -> Vec<Event> {
+        let unit = self.get_unit(unit_id);
+        let mut events = Vec::new();
+        if !self.game_state.is_minefield_known(unit.player_id, mined_pos) {
+            events.push(EventRevealMinefield(mined_pos, unit.player_id));
+        }
+        let damage = self.mine_test(unit_id);
+        events.push(EventMineExplosion(unit_id, damage));
+        if damage >= unit.hit_points {
+            match self.get_wreck_event(unit_id) {
+                Some(event) => events.push(event),
+                None => {},
+            }
+            return events;
+        }
+        let unit_type = self.object_types.get_unit_type(unit.type_id);
+        match unit_type.class {
+            Infantry => {
+                let suppression = self.get_mine_weapon_type().suppression;
+                let morale_state = match unit.morale_state {
+                    Steady | Pinned => Pinned,
+                    Routed => Routed,
+                };
+                events.push(EventSuppressUnit(unit_id, suppression, morale_state));
+            },
+            Vehicle => if damage > 0 {
+                match self.get_damage_vehicle_event(unit_id) {
+                    Some(event) => events.push(event),
+                    None => {},
+                }
+            },
+        }
+        events
+    }
+
+    fn get_mine_weapon_type(&self) -> &WeaponType {
+        let id = self.object_types.get_weapon_type_id("mine");
+        self.object_types.get_weapon_type(id)
+    }
+
+    fn mine_test(&self, unit_id: UnitId) -> MInt {
+        let unit = self.get_unit(unit_id);
+        let unit_type = self.object_types.get_unit_type(unit.type_id);
+        let weapon_type = self.get_mine_weapon_type();
+        let pierce_test_v = self.rules.pierce_base
+            - unit_type.armor + weapon_type.ap;
+        let wound_test_v = self.rules.wound_base
+            - unit_type.toughness + weapon_type.damage;
+        self.damage_test(weapon_type, unit_type, pierce_test_v, wound_test_v)
+    }
+
+    fn command_lay_mines_to_events(&self, unit_id: UnitId, pos: MapPos) -> Vec<Event> {
+        let unit = self.get_unit(unit_id);
+        let unit_type = self.object_types.get_unit_type(unit.type_id);
+        let is_enemy_in_pos = self.game_state.units_at(pos).iter()
+            .any(|u| u.player_id != unit.player_id);
+        if !unit_type.is_engineer() || unit.mines <= 0
+            || unit.player_id != self.current_player_id
+            || unit.move_points == 0 || unit.is_loaded()
+            || !self.is_inboard(pos)
+            || distance(unit.pos, pos) > 1
+            || is_enemy_in_pos
+            || self.game_state.is_minefield_known(unit.player_id, pos)
+        {
+            return vec![];
+        }
+        // Engineer sets off hidden enemy minefield while laying mines
+        if self.game_state.get_minefield(pos).is_some() {
+            return self.get_mine_explosion_events(unit_id, pos);
+        }
+        vec![EventLayMines(unit_id, pos)]
+    }
+
+    // Fortification spends whole turn of the unit
+    fn command_fortify_to_events(&self, unit_id: UnitId) -> Vec<Event> {
+        let unit = self.get_unit(unit_id);
+        if unit.player_id != self.current_player_id
+            || unit.move_points == 0 || unit.is_loaded()
+            || unit.weapons.iter().any(|w| w.attacked)
+        {
+            return vec![];
+        }
+        vec![EventFortify(unit_id)]
+    }
+
+    // Medics and supply units restore lost members of adjacent squads
+    fn command_heal_unit_to_events(
+        &self,
+        healer_id: UnitId,
+        unit_id: UnitId,
+    ) -> Vec<Event> {
+        let healer = self.get_unit(healer_id);
+        let unit = self.get_unit(unit_id);
+        let healer_type = self.object_types.get_unit_type(healer.type_id);
+        let unit_type = self.object_types.get_unit_type(unit.type_id);
+        let is_infantry = match unit_type.class {
+            Infantry => true,
+            Vehicle => false,
+        };
+        if healer_type.medics <= 0 || !is_infantry || healer_id == unit_id
+            || healer.player_id != self.current_player_id
+            || unit.player_id != self.current_player_id
+            || healer.move_points == 0
+            || healer.weapons.iter().any(|w| w.attacked)
+            || healer.is_loaded() || unit.is_loaded()
+            || distance(healer.pos, unit.pos) > 1
+            || unit.hit_points >= unit_type.hit_points
+        {
+            return vec![];
+        }
+        let hit_points = min(
+            healer_type.medics * unit_type.get_member_hit_points(),
+            unit_type.hit_points - unit.hit_points,
+        );
+        vec![EventHealUnit(healer_id, unit_id, hit_points)]
+    }
+
+    fn is_friendly_territory(&self, player_id: PlayerId, pos: MapPos) -> bool {
+        if self.scenario.is_deploy_pos(player_id, pos) {
+            return true;
+        }
+        for id in self.scenario.get_objective_ids().into_iter() {
+            if self.game_state.get_objective_owner(id) == Some(player_id)
+                && distance(self.scenario.get_objective(id).pos, pos) <= 1
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    // Vehicles are repaired in workshops, infantry that didn't move
+    // or fire regains one squad member in friendly territory
+    fn get_restore_events(&self, player_id: PlayerId) -> Vec<Event> {
+        let mut events = Vec::new();
+        for (id, unit) in self.game_state.units.iter() {
+            let unit_type = self.object_types.get_unit_type(unit.type_id);
+            if unit.player_id != player_id || unit.is_loaded() {
+                continue;
+            }
+            let missing_hit_points = unit_type.hit_points - unit.hit_points;
+            match unit_type.class {
+                Vehicle => {
+                    if !self.scenario.is_workshop_pos(player_id, unit.pos) {
+                        continue;
+                    }
+                    if missing_hit_points > 0 {
+                        events.push(EventRestoreUnit(*id, min(
+                            self.scenario.repair_rate, missing_hit_points)));
+                    }
+                    match get_critical_damage(unit) {
+                        Some(damage) => events.push(EventRepairVehicle(*id, damage)),
+                        None => {},
+                    }
+                },
+                Infantry => {
+                    if unit.has_moved_or_fired || missing_hit_points <= 0
+                        || !self.is_friendly_territory(player_id, unit.pos)
+                    {
+                        continue;
+                    }
+                    events.push(EventRestoreUnit(*id, min(
+                        unit_type.get_member_hit_points(), missing_hit_points)));
+                },
+            }
+        }
+        events
+    }
+
+    fn command_clear_mines_to_events(&self, unit_id: UnitId, pos: MapPos) -> Vec<Event> {
+        let unit = self.get_unit(unit_id);
+        let unit_type = self.object_types.get_unit_type(unit.type_id);
+        if !unit_type.is_engineer()
+            || unit.player_id != self.current_player_id
+            || unit.move_points == 0 || unit.is_loaded()
+            || distance(unit.pos, pos) > 1
+            || !self.game_state.is_minefield_known(unit.player_id, pos)
+        {
+            return vec![];
+        }
+        vec![EventClearMines(unit_id, pos)]
+    }
+
+    fn command_attack_stack_to_events(
+        &self,
+        attacker_id: UnitId,
+        pos: MapPos,
+        weapon_choice: WeaponChoice,
+    ) -> Vec<Event> {
+        let attacker = self.get_unit(attacker_id);
+        let mut defender_ids = Vec::new();
+        for unit in self.game_state.units_at(pos).iter() {
+            if unit.player_id != attacker.player_id {
+                defender_ids.push(unit.id);
+            }
+        }
+        match task_rng().choose(defender_ids.as_slice()) {
+            Some(defender_id) => self.command_attack_unit_to_events(
+                attacker_id, *defender_id, weapon_choice),
+            None => vec![],
+        }
+    }
+
+    fn is_inboard(&self, pos: MapPos) -> bool {
+        pos.v.x >= 0 && pos.v.y >= 0
+            && pos.v.x < self.map_size.w && pos.v.y < self.map_size.h
+    }
+
+    fn command_load_unit_to_events(
+        &self,
+        passenger_id: UnitId,
+        transporter_id: UnitId,
+    ) -> Vec<Event> {
+        let passenger = self.get_unit(passenger_id);
+        let transporter = self.get_unit(transporter_id);
+        let passenger_type = self.object_types.get_unit_type(passenger.type_id);
+        let transporter_type
+            = self.object_types.get_unit_type(transporter.type_id);
+        let is_infantry = match passenger_type.class {
+            Infantry => true,
+            Vehicle => false,
+        };
+        let free_places = transporter_type.transport_capacity
+            - transporter.passenger_ids.len() as MInt;
+        if !is_infantry || free_places <= 0
+            || passenger.player_id != self.current_player_id
+            || transporter.player_id != self.current_player_id
+            || passenger.move_points == 0
+            || passenger.is_loaded()
+            || transporter.is_loaded()
+            || distance(passenger.pos, transporter.pos) != 1
+        {
+            return vec![];
+        }
+        vec![EventLoadUnit(passenger_id, transporter_id)]
+    }
+
+    fn command_unload_unit_to_events(
+        &self,
+        passenger_id: UnitId,
+        pos: MapPos,
+    ) -> Vec<Event> {
+        let passenger = self.get_unit(passenger_id);
+        let transporter_id = match passenger.transporter_id {
+            Some(transporter_id) => transporter_id,
+            None => return vec![],
+        };
+        let transporter = self.get_unit(transporter_id);
+        if passenger.player_id != self.current_player_id
+            || passenger.move_points == 0
+            || !self.is_inboard(pos)
+            || distance(transporter.pos, pos) != 1
+            || !self.can_stack(pos, passenger.player_id, passenger.type_id)
+        {
+            return vec![];
+        }
+        vec![EventUnloadUnit(passenger_id, pos)]
+    }
+
+    // Passengers of destroyed transporter try to bail out
+    // to free neighbour tiles, unlucky ones are destroyed
+    fn get_passengers_events(&self, transporter_id: UnitId) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut reserved_positions = Vec::new();
+        for (id, unit) in self.game_state.units.iter() {
+            if unit.transporter_id != Some(transporter_id) {
+                continue;
+            }
+            let mut free_pos = None;
+            for pos in MapPosIter::new(self.map_size) {
+                if distance(unit.pos, pos) == 1
+                    && self.can_stack(pos, unit.player_id, unit.type_id)
+                    && !reserved_positions.iter().any(|p| *p == pos)
+                {
+                    free_pos = Some(pos);
+                    break;
+                }
+            }
+            let toughness = self.object_types.get_unit_type(unit.type_id).toughness;
+            match free_pos {
+                Some(pos) if self.rules.test(toughness) => {
+                    reserved_positions.push(pos);
+                    events.push(EventUnloadUnit(*id, pos));
+                    let morale_state = match unit.morale_state {
+                        Steady | Pinned => Pinned,
+                        Routed => Routed,
+                    };
+                    events.push(EventSuppressUnit(
+                        *id, BAIL_OUT_SUPPRESSION, morale_state));
+                },
+                _ => {
+                    events.push(EventDestroyUnit(*id));
+                },
+            }
+        }
+        events
+    }
+
+    fn command_deploy_unit_to_events(
+        &mut self,
+        type_id: UnitTypeId,
+        pos: MapPos,
+    ) -> Vec<Event> {
+        let player_id = self.current_player_id;
+        let roster_unit = match self.get_roster(player_id).iter()
+            .find(|unit| unit.type_id.id == type_id.id)
+        {
+            Some(unit) => unit.clone(),
+            None => return vec![],
+        };
+        if !self.scenario.is_in_deploy_zone(player_id, pos)
+            || !self.can_stack(pos, player_id, type_id)
+        {
+            return vec![];
+        }
+        let unit_id = self.get_new_unit_id();
+        let mut events = vec![EventCreateUnit(unit_id, pos, type_id, player_id)];
+        if roster_unit.experience > 0 {
+            events.push(EventGainExperience(unit_id, roster_unit.experience));
+        }
+        events
+    }
+
+    // All roster units must be deployed before player is ready
+    fn command_ready_to_events(&self) -> Vec<Event> {
+        let player_id = self.current_player_id;
+        if self.get_roster(player_id).len() != 0 {
+            return vec![];
+        }
+        vec![EventPlayerReady(player_id)]
+    }
+
+    fn deployment_command_to_events(&mut self, command: Command) -> Vec<Event> {
+        match command {
+            CommandDeployUnit(type_id, pos) => {
+                self.command_deploy_unit_to_events(type_id, pos)
+            },
+            CommandReady => self.command_ready_to_events(),
+            _ => vec![],
+        }
+    }
+
+    fn command_to_events(&mut self, command: Command) -> Vec<Event> {
+        if self.phase == Deployment {
+            return self.deployment_command_to_events(command);
+        }
+        match command {
+            CommandDeployUnit(_, _)
+                | CommandReady
+                | CommandUndo
+                | CommandQueueMove(_, _)
+                | CommandMoveGroup(_) => vec![],
+            CommandEndTurn => {
+                let old_id = self.current_player_id.id;
+                let max_id = self.players.len() as MInt;
+                let new_id = if old_id + 1 == max_id {
+                    0
+                } else {
+                    old_id + 1
+                };
+                let old_player_id = PlayerId{id: old_id};
+                let mut events = self.get_capture_objective_events(old_player_id);
+                events.push_all_move(self.get_restore_events(old_player_id));
+                events.push(EventEndTurn(old_player_id, PlayerId{id: new_id}));
+                events
+            },
+            CommandBuyUnit(type_id, pos) => {
+                self.command_buy_unit_to_events(type_id, pos)
+            },
+            CommandMove(unit_id, path) => {
+                self.command_move_to_events(unit_id, path)
+            },
+            CommandAttackUnit(attacker_id, defender_id, weapon_choice) => {
+                self.command_attack_unit_to_events(
+                    attacker_id, defender_id, weapon_choice)
+            },
+            CommandAttackStack(attacker_id, pos, weapon_choice) => {
+                self.command_attack_stack_to_events(
+                    attacker_id, pos, weapon_choice)
+            },
+            CommandLoadUnit(passenger_id, transporter_id) => {
+                self.command_load_unit_to_events(passenger_id, transporter_id)
+            },
+            CommandUnloadUnit(passenger_id, pos) => {
+                self.command_unload_unit_to_events(passenger_id, pos)
+            },
+            CommandAttackPos(attacker_id, pos, weapon_index) => {
+                self.command_attack_pos_to_events(
+                    attacker_id, pos, weapon_index)
+            },
+            CommandLayMines(unit_id, pos) => {
+                self.command_lay_mines_to_events(unit_id, pos)
+            },
+            CommandClearMines(unit_id, pos) => {
+                self.command_clear_mines_to_events(unit_id, pos)
+            },
+            CommandFortify(unit_id) => {
+                self.command_fortify_to_events(unit_id)
+            },
+            CommandHealUnit(healer_id, unit_id) => {
+                self.command_heal_unit_to_events(healer_id, unit_id)
+            },
+        }
+    }
+
+    // Only move and attack orders are supported in simultaneous mode
+    fn do_simultaneous_command(&mut self, command: Command) {
+        let player_id = self.current_player_id;
+        let order = match command {
+            CommandMove(unit_id, path) => {
+                if self.command_move_to_events(unit_id, path.clone()).len() == 0 {
+                    return;
+                }
+                OrderMove(unit_id, path)
+            },
+            CommandAttackUnit(attacker_id, defender_id, weapon_choice) => {
+                let attacker = self.get_unit(attacker_id);
+                let defender = self.get_unit(defender_id);
+                if attacker.player_id != player_id
+                    || defender.player_id == player_id
+                {
+                    return;
+                }
+                OrderAttack(attacker_id, defender_id, weapon_choice)
+            },
+            CommandMoveGroup(moves) => {
+                for (unit_id, path) in moves.into_iter() {
+                    self.do_simultaneous_command(CommandMove(unit_id, path));
+                }
+                return;
+            },
+            CommandEndTurn => {
+                self.do_core_event(EventOrdersDone(player_id));
+                if self.orders_done_player_ids.len() == self.players.len() {
+                    self.resolve_orders();
+                }
+                return;
+            },
+            _ => return,
+        };
+        let unit_id = match order {
+            OrderMove(unit_id, _) | OrderAttack(unit_id, _, _) => unit_id,
+        };
+        if self.get_unit(unit_id).player_id != player_id {
+            return;
+        }
+        let orders = self.orders.get_mut(&player_id);
+        // New order replaces old order of the same kind
+        orders.retain(|old_order| match (old_order, &order) {
+            (&OrderMove(id, _), &OrderMove(_, _))
+                | (&OrderAttack(id, _, _), &OrderAttack(_, _, _)) => id != unit_id,
+            _ => true,
+        });
+        orders.push(order);
+    }
+
+    fn resolve_attack_order(
+        &mut self,
+        attacker_id: UnitId,
+        defender_id: UnitId,
+        weapon_choice: WeaponChoice,
+    ) {
+        if self.game_state.units.find(&attacker_id).is_none()
+            || self.game_state.units.find(&defender_id).is_none()
+        {
+            return;
+        }
+        let events = self.command_attack_unit_to_events(
+            attacker_id, defender_id, weapon_choice);
+        for event in events.into_iter() {
+            self.do_core_event(event);
+        }
+    }
+
+    // Unit stops before tiles that are taken by enemies in this turn
+    fn resolve_move_order(&mut self, unit_id: UnitId, path: Vec<MapPos>) {
+        let player_id = match self.game_state.units.find(&unit_id) {
+            Some(unit) if unit.pos == path[0] => unit.player_id,
+            _ => return,
+        };
+        let mut len = 1;
+        while len < path.len() {
+            let has_enemies = self.game_state.units_at(path[len]).iter()
+                .any(|unit| unit.player_id != player_id);
+            if has_enemies {
+                break;
+            }
+            len += 1;
+        }
+        while len >= 2 {
+            let events = self.command_move_to_events(
+                unit_id, path.slice_to(len).to_vec());
+            if events.len() != 0 {
+                for event in events.into_iter() {
+                    self.do_core_event(event);
+                }
+                return;
+            }
+            len -= 1;
+        }
+    }
+
+    // Fire is resolved before movement. In both phases players
+    // take turns order by order, first player changes every round.
+    fn resolve_orders(&mut self) {
+        let players_count = self.players.len();
+        let first = self.round as uint % players_count;
+        let mut player_ids = Vec::new();
+        for i in range(0, players_count) {
+            player_ids.push(self.players[(first + i) % players_count].id);
+        }
+        let max_orders_count = self.orders.values()
+            .map(|orders| orders.len()).max().unwrap_or(0);
+        for is_move_phase in [false, true].iter() {
+            for i in range(0, max_orders_count) {
+                for player_id in player_ids.iter() {
+                    if self.is_game_over {
+                        return;
+                    }
+                    let order = match self.orders[*player_id].as_slice().get(i) {
+                        Some(order) => order.clone(),
+                        None => continue,
+                    };
+                    self.current_player_id = *player_id;
+                    match order {
+                        OrderAttack(attacker_id, defender_id, weapon_choice) => {
+                            if !*is_move_phase {
+                                self.resolve_attack_order(
+                                    attacker_id, defender_id, weapon_choice);
+                            }
+                        },
+                        OrderMove(unit_id, path) => {
+                            if *is_move_phase {
+                                self.resolve_move_order(unit_id, path);
+                            }
+                        },
+                    }
+                }
+            }
+        }
+        self.orders = get_empty_orders();
+        self.orders_done_player_ids.clear();
+        // Usual end of turn for every player, round ends with first player
+        for player in get_players_list().iter() {
+            if self.is_game_over {
+                return;
+            }
+            self.current_player_id = player.id;
+            for event in self.command_to_events(CommandEndTurn).into_iter() {
+                self.do_core_event(event);
+            }
+        }
+    }
+
+    fn get_spotted_enemy_ids(&self, player_id: PlayerId) -> Vec<UnitId> {
+        let mut ids = Vec::new();
+        for unit in self.game_state.units.values() {
+            if unit.player_id != player_id
+                && self.is_pos_spotted(player_id, unit.pos)
+            {
+                ids.push(unit.id);
+            }
+        }
+        ids
+    }
+
+    fn is_path_blocked(&self, player_id: PlayerId, path: &Vec<MapPos>) -> bool {
+        path.iter().any(|pos| {
+            self.game_state.units_at(*pos).iter()
+                .any(|unit| unit.player_id != player_id)
+        })
+    }
+
+    // Longest part of the path that unit can move this turn
+    fn get_queued_move_events(
+        &self,
+        unit_id: UnitId,
+        path: &Vec<MapPos>,
+    ) -> (uint, Vec<Event>) {
+        let move_points = self.get_unit(unit_id).move_points;
+        let mut len = 1;
+        let mut cost = 0;
+        while len < path.len() {
+            cost += self.game_state.get_move_cost(path[len]);
+            if cost > move_points {
+                break;
+            }
+            len += 1;
+        }
+        while len >= 2 {
+            let events = self.command_move_to_events(
+                unit_id, path.slice_to(len).to_vec());
+            if events.len() != 0 {
+                return (len, events);
+            }
+            len -= 1;
+        }
+        (0, vec![])
+    }
+
+    // Order is cancelled if path is blocked or new enemy is spotted
+    fn continue_queued_move(&mut self, unit_id: UnitId) {
+        let queued_move = match self.queued_moves.pop(&unit_id) {
+            Some(queued_move) => queued_move,
+            None => return,
+        };
+        let (player_id, pos, move_points) = match self.game_state.units.find(&unit_id) {
+            Some(unit) => (unit.player_id, unit.pos, unit.move_points),
+            None => return,
+        };
+        let has_new_enemies = self.get_spotted_enemy_ids(player_id).iter()
+            .any(|id| !queued_move.known_enemy_ids.contains(id));
+        if queued_move.path[0] != pos || has_new_enemies
+            || self.is_path_blocked(player_id, &queued_move.path)
+        {
+            return;
+        }
+        if move_points == 0 {
+            self.queued_moves.insert(unit_id, queued_move);
+            return;
+        }
+        let (len, events) = self.get_queued_move_events(unit_id, &queued_move.path);
+        let is_plain_move = events.len() == 1;
+        for event in events.into_iter() {
+            self.do_core_event(event);
+        }
+        // Minefield stops the march
+        if len < 2 || len == queued_move.path.len() || !is_plain_move {
+            return;
+        }
+        let spotted_enemy_ids = self.get_spotted_enemy_ids(player_id);
+        if spotted_enemy_ids.iter().any(|id| !queued_move.known_enemy_ids.contains(id)) {
+            return;
+        }
+        self.queued_moves.insert(unit_id, QueuedMove {
+            path: queued_move.path.slice_from(len - 1).to_vec(),
+            known_enemy_ids: queued_move.known_enemy_ids,
+        });
+    }
+
+    fn queue_move(&mut self, unit_id: UnitId, path: Vec<MapPos>) {
+        let player_id = match self.game_state.units.find(&unit_id) {
+            Some(unit) if path.len() >= 2 && path[0] == unit.pos => unit.player_id,
+            _ => return,
+        };
+        if player_id != self.current_player_id {
+            return;
+        }
+        let known_enemy_ids = self.get_spotted_enemy_ids(player_id);
+        self.queued_moves.insert(unit_id, QueuedMove {
+            path: path,
+            known_enemy_ids: known_enemy_ids,
+        });
+        self.continue_queued_move(unit_id);
+    }
+
+    fn continue_queued_moves(&mut self) {
+        let player_id = self.current_player_id;
+        let mut unit_ids = Vec::new();
+        for unit_id in self.queued_moves.keys() {
+            match self.game_state.units.find(unit_id) {
+                Some(unit) if unit.player_id == player_id => unit_ids.push(*unit_id),
+                _ => {},
+            }
+        }
+        unit_ids.sort();
+        for unit_id in unit_ids.iter() {
+            if self.is_game_over {
+                return;
+            }
+            self.continue_queued_move(*unit_id);
+        }
+    }
+
+    // Earlier moves can free tiles for later ones
+    fn move_group(&mut self, moves: Vec<(UnitId, Vec<MapPos>)>) {
+        for (unit_id, path) in moves.into_iter() {
+            if self.is_game_over {
+                return;
+            }
+            let is_valid = match self.game_state.units.find(&unit_id) {
+                Some(unit) => unit.player_id == self.current_player_id
+                    && path.len() >= 2 && path[0] == unit.pos,
+                None => false,
+            };
+            if !is_valid {
+                continue;
+            }
+            let _ = self.queued_moves.pop(&unit_id);
+            let events = self.command_move_to_events(unit_id, path);
+            for event in events.into_iter() {
+                self.do_core_event(event);
+            }
+        }
+    }
+
+    fn get_move_undo_info(&self, command: &Command) -> Option<MoveUndoInfo> {
+        match *command {
+            CommandMove(unit_id, ref path) => {
+                self.game_state.units.find(&unit_id).map(|unit| MoveUndoInfo {
+                    unit_id: unit_id,
+                    path: path.clone(),
+                    move_points: unit.move_points,
+                    is_fortified: unit.is_fortified,
+                    fortification: unit.fortification,
+                    has_moved_or_fired: unit.has_moved_or_fired,
+                    queued_move: None,
+                })
+            },
+            _ => None,
+        }
+    }
+
+    fn undo_last_move(&mut self) {
+        match self.last_move.take() {
+            Some(info) => {
+                match info.queued_move {
+                    Some(ref queued_move) => {
+                        self.queued_moves.insert(info.unit_id, queued_move.clone());
+                    },
+                    None => {},
+                }
+                self.do_core_event(EventUndoMove(info));
+            },
+            None => {},
+        }
+    }
+
+    pub fn do_command(&mut self, command: Command) {
+        if self.is_game_over {
+            return;
+        }
+        match command {
+            CommandUndo => {
+                self.undo_last_move();
+                return;
+            },
+            _ => {},
+        }
+        self.last_move = None;
+        if self.phase == Battle && self.turn_mode == Simultaneous {
+            self.do_simultaneous_command(command);
+            return;
+        }
+        let mut undo_info = self.get_move_undo_info(&command);
+        let is_end_turn = match command {
+            CommandMoveGroup(ref moves) => {
+                self.move_group(moves.clone());
+                return;
+            },
+            CommandQueueMove(unit_id, ref path) => {
+                self.queue_move(unit_id, path.clone());
+                return;
+            },
+            // Direct order replaces queued move
+            CommandMove(unit_id, _) => {
+                let queued_move = self.queued_moves.pop(&unit_id);
+                match undo_info {
+                    Some(ref mut info) => info.queued_move = queued_move,
+                    None => {},
+                }
+                false
+            },
+            CommandEndTurn => true,
+            _ => false,
+        };
+        let fired_triggers_count = self.fired_trigger_ids.len();
+        let events = self.command_to_events(command);
+        // Minefields and dice rolls produce additional events
+        let is_plain_move = events.len() == 1 && match events[0] {
+            EventMove(_, _) => true,
+            _ => false,
+        };
+        for event in events.into_iter() {
+            self.do_core_event(event);
+        }
+        if is_plain_move && self.fired_trigger_ids.len() == fired_triggers_count {
+            self.last_move = undo_info;
+        }
+        if is_end_turn {
+            self.continue_queued_moves();
+        }
+    }
+
+    fn do_core_event(&mut self, core_event: Event) {
+        self.core_event_list.push(core_event);
+        self.make_events();
+    }
+
+    fn apply_event(&mut self, event: &Event) {
+        match *event {
+            EventEndTurn(old_player_id, new_player_id) => {
+                self.turn += 1;
+                if new_player_id == self.players[0].id {
+                    self.round += 1;
+                }
+                for player in self.players.iter() {
+                    if player.id == new_player_id {
+                        if self.current_player_id == old_player_id {
+                            self.current_player_id = player.id;
+                        }
+                        return;
+                    }
+                }
+            },
+            EventVictory(player_id) => {
+                self.winner_id = Some(player_id);
+                self.is_game_over = true;
+            },
+            EventDraw => {
+                self.is_game_over = true;
+            },
+            EventOrdersDone(player_id) => {
+                self.orders_done_player_ids.push(player_id);
+                let next_player_id = self.players.iter()
+                    .map(|p| p.id)
+                    .find(|id| !self.orders_done_player_ids.iter().any(|d| d == id));
+                match next_player_id {
+                    Some(id) => self.current_player_id = id,
+                    None => {},
+                }
+            },
+            EventChangeObjective(id, score, income) => {
+                let objective = self.scenario.objectives.get_mut(id.id as uint);
+                objective.score = score;
+                objective.income = income;
+            },
+            EventCreateUnit(_, _, type_id, player_id) if self.phase == Deployment => {
+                let roster = self.rosters.get_mut(&player_id);
+                let index = roster.iter()
+                    .position(|unit| unit.type_id.id == type_id.id);
+                match index {
+                    Some(index) => { roster.remove(index); },
+                    None => {},
+                }
+            },
+            EventPlayerReady(player_id) => {
+                self.ready_player_ids.push(player_id);
+                let next_player_id = self.players.iter()
+                    .map(|p| p.id)
+                    .find(|id| !self.ready_player_ids.iter().any(|r| r == id));
+                match next_player_id {
+                    Some(id) => self.current_player_id = id,
+                    None => {
+                        self.phase = Battle;
+                        self.current_player_id = self.players[0].id;
+                    },
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn get_min_enemy_distance(&self, player_id: PlayerId, pos: MapPos) -> Option<MInt> {
+        let mut min_distance = None;
+        for (_, unit) in self.game_state.units.iter() {
+            if unit.player_id == player_id {
+                continue;
+            }
+            let d = distance(unit.pos, pos);
+            min_distance = match min_distance {
+                Some(min_distance) if min_distance <= d => Some(min_distance),
+                _ => Some(d),
+            };
+        }
+        min_distance
+    }
+
+    // Finds reachable tile that is most distant from enemies
+    fn get_flee_path(
+        &mut self,
+        unit_id: UnitId,
+        reserved_positions: &[MapPos],
+    ) -> Option<Vec<MapPos>> {
+        let unit = &self.game_state.units[unit_id];
+        let mut best_pos = unit.pos;
+        let mut best_distance = match self.get_min_enemy_distance(
+            unit.player_id, unit.pos)
+        {
+            Some(d) => d,
+            None => return None,
+        };
+        self.pathfinder.fill_map(&self.game_state, unit);
+        for pos in MapPosIter::new(self.map_size) {
+            let cost = self.pathfinder.get_map().tile(pos).cost;
+            if cost == 0 || cost > unit.move_points
+                || reserved_positions.iter().any(|p| *p == pos)
+                || !self.can_stack(pos, unit.player_id, unit.type_id)
+            {
+                continue;
+            }
+            let d = self.get_min_enemy_distance(unit.player_id, pos).unwrap();
+            if d > best_distance {
+                best_pos = pos;
+                best_distance = d;
+            }
+        }
+        if best_pos == unit.pos {
+            None
+        } else {
+            Some(self.pathfinder.get_path(best_pos))
+        }
+    }
+
+    fn get_flee_events(&mut self, player_id: PlayerId) -> Vec<Event> {
+        let mut routed_unit_ids = Vec::new();
+        for (id, unit) in self.game_state.units.iter() {
+            if unit.player_id == player_id && unit.morale_state == Routed
+                && unit.move_points > 0 && !unit.is_loaded()
+            {
+                routed_unit_ids.push(*id);
+            }
+        }
+        let mut events = Vec::new();
+        let mut reserved_positions = Vec::new();
+        for id in routed_unit_ids.into_iter() {
+            match self.get_flee_path(id, reserved_positions.as_slice()) {
+                Some(path) => {
+                    reserved_positions.push(*path.last().unwrap());
+                    events.push_all_move(self.get_move_events(id, path));
+                },
+                None => {},
+            }
+        }
+        events
+    }
+
+    // Tiles of units that are created by not yet applied events
+    fn get_pending_unit_positions(&self) -> Vec<MapPos> {
+        let mut positions = Vec::new();
+        for event in self.core_event_list.iter() {
+            match *event {
+                EventCreateUnit(_, pos, _, _) => positions.push(pos),
+                _ => {},
+            }
+        }
+        positions
+    }
+
+    // Nearest tile without any units
+    fn get_free_pos(&self, pos: MapPos, taken: &Vec<MapPos>) -> Option<MapPos> {
+        let pending_positions = self.get_pending_unit_positions();
+        let mut best_pos = None;
+        let mut best_distance = 0;
+        for candidate in MapPosIter::new(self.map_size) {
+            if self.game_state.units_at(candidate).len() != 0
+                || taken.iter().any(|p| *p == candidate)
+                || pending_positions.iter().any(|p| *p == candidate)
+            {
+                continue;
+            }
+            let dist = distance(pos, candidate);
+            if best_pos.is_none() || dist < best_distance {
+                best_pos = Some(candidate);
+                best_distance = dist;
+            }
+        }
+        best_pos
+    }
+
+    // 'taken' are tiles of units that are already spawned in this batch
+    fn get_spawn_events(
+        &mut self,
+        player_id: PlayerId,
+        unit_type_names: &Vec<String>,
+        entry_positions: &Vec<MapPos>,
+        taken: &mut Vec<MapPos>,
+    ) -> Vec<Event> {
+        let mut events = Vec::new();
+        for (i, name) in unit_type_names.iter().enumerate() {
+            let entry_pos = entry_positions[i % entry_positions.len()];
+            let pos = match self.get_free_pos(entry_pos, taken) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            let type_id = self.object_types.get_unit_type_id(name.as_slice());
+            let unit_id = self.get_new_unit_id();
+            taken.push(pos);
+            events.push(EventCreateUnit(unit_id, pos, type_id, player_id));
+        }
+        events
+    }
+
+    fn get_reinforcement_events(&mut self, player_id: PlayerId) -> Vec<Event> {
+        let mut reinforcements = Vec::new();
+        for reinforcement in self.scenario.reinforcements.iter() {
+            if reinforcement.player_id == player_id
+                && reinforcement.round == self.round
+            {
+                reinforcements.push((
+                    reinforcement.unit_type_names.clone(),
+                    reinforcement.entry_positions.clone(),
+                ));
+            }
+        }
+        let mut events = Vec::new();
+        let mut taken = Vec::new();
+        for &(ref names, ref entry_positions) in reinforcements.iter() {
+            events.push_all_move(self.get_spawn_events(
+                player_id, names, entry_positions, &mut taken));
+        }
+        events
+    }
+
+    fn is_condition_true(&self, condition: &TriggerCondition) -> bool {
+        match *condition {
+            ConditionRound(round) => self.round >= round,
+            ConditionUnitInArea(player_id, ref area) => {
+                self.game_state.units.values().any(|unit| {
+                    unit.player_id == player_id
+                        && area.iter().any(|pos| *pos == unit.pos)
+                })
+            },
+            ConditionUnitTypeDestroyed(player_id, ref name) => {
+                let type_id = self.object_types.get_unit_type_id(name.as_slice());
+                self.losses.iter().any(|&(id, loss_type_id)| {
+                    id == player_id && loss_type_id.id == type_id.id
+                })
+            },
+            ConditionObjectiveCaptured(objective_id, player_id) => {
+                self.game_state.get_objective_owner(objective_id) == Some(player_id)
+            },
+        }
+    }
+
+    fn get_trigger_action_events(
+        &mut self,
+        actions: &Vec<TriggerAction>,
+        taken: &mut Vec<MapPos>,
+    ) -> Vec<Event> {
+        let mut events = Vec::new();
+        for action in actions.iter() {
+            match *action {
+                ActionSpawnUnits(player_id, ref names, ref entry_positions) => {
+                    events.push_all_move(self.get_spawn_events(
+                        player_id, names, entry_positions, taken));
+                },
+                ActionShowMessage(ref text) => {
+                    events.push(EventShowMessage(text.clone()));
+                },
+                ActionChangeObjective(id, score, income) => {
+                    events.push(EventChangeObjective(id, score, income));
+                },
+                ActionGrantResources(player_id, resources) => {
+                    events.push(EventChangeResources(player_id, resources));
+                },
+                ActionEndGame(winner_id) => {
+                    events.push(EventVictory(winner_id));
+                },
+            }
+        }
+        events
+    }
+
+    // Every trigger fires only once
+    fn get_trigger_events(&mut self) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut taken = Vec::new();
+        for i in range(0, self.scenario.triggers.len()) {
+            if self.fired_trigger_ids.contains(&i) {
+                continue;
+            }
+            let actions = {
+                let trigger = &self.scenario.triggers[i];
+                if !trigger.conditions.iter().all(|c| self.is_condition_true(c)) {
+                    continue;
+                }
+                trigger.actions.clone()
+            };
+            let action_events = self.get_trigger_action_events(&actions, &mut taken);
+            events.push_all_move(action_events);
+            self.fired_trigger_ids.push(i);
+        }
+        events
+    }
+
+    fn get_unit_list(&self) -> Vec<(UnitId, PlayerId, UnitTypeId)> {
+        self.game_state.units.values()
+            .map(|unit| (unit.id, unit.player_id, unit.type_id))
+            .collect()
+    }
+
+    fn update_losses(&mut self, old_units: Vec<(UnitId, PlayerId, UnitTypeId)>) {
+        for &(id, player_id, type_id) in old_units.iter() {
+            if self.game_state.units.find(&id).is_none() {
+                self.losses.push((player_id, type_id));
+            }
+        }
+    }
+
+    // Events that are caused by already applied event
+    fn get_follow_up_events(&mut self, event: &Event) -> Vec<Event> {
+        match *event {
+            EventEndTurn(_, new_player_id) => {
+                if new_player_id == self.players[0].id
+                    && self.round > self.scenario.round_limit
+                {
+                    return vec![self.get_round_limit_event()];
+                }
+                let mut events = vec![EventChangeResources(
+                    new_player_id, self.get_income(new_player_id))];
+                let score = self.get_objectives_score(new_player_id);
+                if score != 0 {
+                    events.push(EventChangeScore(new_player_id, score));
+                }
+                events.push_all_move(self.get_flee_events(new_player_id));
+                events.push_all_move(
+                    self.get_reinforcement_events(new_player_id));
+                events
+            },
+            EventGainExperience(unit_id, _) => {
+                let unit = self.get_unit(unit_id);
+                let rank = get_rank(unit.experience);
+                if rank > unit.rank {
+                    vec![EventPromoteUnit(unit_id, rank)]
+                } else {
+                    vec![]
+                }
+            },
+            EventAttackUnit(attacker_id, defender_id, _, _) => {
+                let killed = self.game_state.units.find(&defender_id).is_none();
+                let mut events = Vec::new();
+                let attacker = self.get_unit(attacker_id);
+                let rank = get_rank(attacker.experience);
+                if rank > attacker.rank {
+                    events.push(EventPromoteUnit(attacker_id, rank));
+                }
+                if killed {
+                    events.push_all_move(
+                        self.get_passengers_events(defender_id));
+                }
+                events
+            },
+            EventIndirectAttack(attacker_id, _, _, ref hits) => {
+                let mut events = Vec::new();
+                match self.game_state.units.find(&attacker_id) {
+                    Some(attacker) => {
+                        let rank = get_rank(attacker.experience);
+                        if rank > attacker.rank {
+                            events.push(EventPromoteUnit(attacker_id, rank));
+                        }
+                    },
+                    None => {}, // killed by own shell
+                }
+                for hit in hits.iter() {
+                    if self.game_state.units.find(&hit.unit_id).is_none() {
+                        events.push_all_move(
+                            self.get_passengers_events(hit.unit_id));
+                    }
+                }
+                events
+            },
+            EventDestroyUnit(unit_id) => {
+                self.get_passengers_events(unit_id)
+            },
+            EventPlayerReady(_) if self.phase == Battle => {
+                let mut events = Vec::new();
+                for (id, unit) in self.game_state.units.iter() {
+                    events.push(EventRevealUnit(*id, unit.pos, unit.type_id,
+                        unit.player_id, unit.experience, unit.rank));
+                }
+                events
+            },
+            EventMineExplosion(unit_id, _) => {
+                if self.game_state.units.find(&unit_id).is_none() {
+                    self.get_passengers_events(unit_id)
+                } else {
+                    vec![]
+                }
+            },
+            EventChangeScore(player_id, _) => {
+                let score = self.game_state.get_score(player_id);
+                if !self.is_game_over
+                    && score >= self.scenario.victory_score
+                {
+                    vec![EventVictory(player_id)]
+                } else {
+                    vec![]
+                }
+            },
+            _ => vec![],
+        }
+    }
+
+    // Hides minefields from players that don't know about them
+    // Called before the event is applied
+    fn is_event_visible(&self, player_id: PlayerId, event: &Event) -> bool {
+        match *event {
+            EventLayMines(unit_id, _) => {
+                self.get_unit(unit_id).player_id == player_id
+            },
+            EventClearMines(_, pos) => {
+                self.game_state.is_minefield_known(player_id, pos)
+            },
+            EventRevealMinefield(_, minefield_player_id) => {
+                minefield_player_id == player_id
+            },
+            EventCreateUnit(_, _, _, owner_id) if self.phase == Deployment => {
+                owner_id == player_id
+            },
+            EventGainExperience(unit_id, _) | EventPromoteUnit(unit_id, _)
+                if self.phase == Deployment =>
+            {
+                self.get_unit(unit_id).player_id == player_id
+            },
+            EventRevealUnit(_, _, _, owner_id, _, _) => owner_id != player_id,
+            _ => true,
+        }
+    }
+
+    fn make_events(&mut self) {
+        while self.core_event_list.len() != 0 {
+            let event = self.core_event_list.remove(0).unwrap();
+            let old_units = self.get_unit_list();
+            let visible_player_ids: Vec<PlayerId> = self.players.iter()
+                .filter(|player| self.is_event_visible(player.id, &event))
+                .map(|player| player.id)
+                .collect();
+            self.apply_event(&event);
             self.game_state.apply_event(&self.object_types, &event);
-            for player in self.players.iter() {
-                let event_list = self.event_lists.get_mut(&player.id);
-                // TODO: per player event filter
+            self.update_losses(old_units);
+            for player_id in visible_player_ids.iter() {
+                let event_list = self.event_lists.get_mut(player_id);
                 event_list.push(event.clone());
             }
+            for follow_up_event in self.get_follow_up_events(&event).into_iter() {
+                self.core_event_list.push(follow_up_event);
+            }
+            if self.phase == Battle && !self.is_game_over {
+                for trigger_event in self.get_trigger_events().into_iter() {
+                    self.core_event_list.push(trigger_event);
+                }
+            }
         }
     }
 }
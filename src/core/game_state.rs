@@ -1,44 +1,330 @@
 // See LICENSE file for copyright and license details.
 
+use std::cmp::{max, min};
 use std::collections::hashmap::HashMap;
 use core::core::{
     ObjectTypes,
+    UnitType,
+    UnitTypeId,
     Unit,
+    Weapon,
+    IN_COMMAND_AURA,
+    OUT_OF_COMMAND_AURA,
+    TileEffect,
+    Minefield,
+    Steady,
+    Pinned,
+    Routed,
     Event,
     EventMove,
     EventEndTurn,
     EventCreateUnit,
     EventAttackUnit,
+    EventChangeResources,
+    EventCaptureObjective,
+    EventChangeScore,
+    EventVictory,
+    EventSuppressUnit,
+    EventPromoteUnit,
+    EventLoadUnit,
+    EventUnloadUnit,
+    EventDestroyUnit,
+    EventDamageVehicle,
+    EventIndirectAttack,
+    EventCreateTileEffect,
+    EventLayMines,
+    EventClearMines,
+    EventRevealMinefield,
+    EventMineExplosion,
+    EventFortify,
+    EventHealUnit,
+    EventRestoreUnit,
+    EventRepairVehicle,
+    EventRevealUnit,
+    EventPlayerReady,
+    EventShowMessage,
+    EventChangeObjective,
+    EventGainExperience,
+    EventDraw,
+    EventOrdersDone,
+    EventUndoMove,
+    Immobilized,
+    WeaponDestroyed,
 };
-use core::types::{PlayerId, UnitId, MapPos};
+use core::types::{MInt, PlayerId, UnitId, MapPos, ObjectiveId};
+use core::map::{distance};
+
+const SUPPRESSION_RECOVERY: MInt = 2;
+const EXPERIENCE_FOR_ATTACK: MInt = 1;
+const EXPERIENCE_FOR_KILL: MInt = 3;
+const MAX_FORTIFICATION: MInt = 3;
+
+// Every unit in a stack needs at least one of six hex triangles
+const MAX_UNITS_IN_STACK: uint = 6;
+
+fn recover_morale(unit: &mut Unit, unit_type: &UnitType) {
+    unit.suppression = max(0, unit.suppression - SUPPRESSION_RECOVERY);
+    if unit.suppression < unit_type.morale + unit.rank + unit.aura.morale {
+        unit.morale_state = match unit.morale_state {
+            Routed => Pinned,
+            Pinned | Steady => Steady,
+        };
+    }
+}
+
+fn get_move_points(unit: &Unit, unit_type: &UnitType) -> MInt {
+    if unit.immobilized {
+        return 0;
+    }
+    match unit.morale_state {
+        Steady => max(0, unit_type.move_points + unit.aura.move_points
+            - unit.suppression / 2),
+        Pinned => 0,
+        Routed => unit_type.move_points,
+    }
+}
 
 pub struct GameState {
     pub units: HashMap<UnitId, Unit>,
+    pub resources: HashMap<PlayerId, MInt>,
+    pub scores: HashMap<PlayerId, MInt>,
+    pub objective_owners: HashMap<ObjectiveId, PlayerId>,
+    pub tile_effects: Vec<TileEffect>,
+    pub minefields: Vec<Minefield>,
 }
 
 impl<'a> GameState {
     pub fn new() -> GameState {
         GameState {
             units: HashMap::new(),
+            resources: HashMap::new(),
+            scores: HashMap::new(),
+            objective_owners: HashMap::new(),
+            tile_effects: Vec::new(),
+            minefields: Vec::new(),
+        }
+    }
+
+    pub fn get_minefield(&self, pos: MapPos) -> Option<&Minefield> {
+        self.minefields.iter().find(|m| m.pos == pos)
+    }
+
+    pub fn is_minefield_known(&self, player_id: PlayerId, pos: MapPos) -> bool {
+        match self.get_minefield(pos) {
+            Some(minefield) => minefield.known_by.iter().any(|id| *id == player_id),
+            None => false,
+        }
+    }
+
+    fn reveal_minefield(&mut self, pos: MapPos, player_id: PlayerId) {
+        if self.is_minefield_known(player_id, pos) {
+            return;
+        }
+        match self.minefields.iter_mut().find(|m| m.pos == pos) {
+            Some(minefield) => {
+                minefield.known_by.push(player_id);
+                return;
+            },
+            None => {},
+        }
+        self.minefields.push(Minefield {
+            pos: pos,
+            known_by: vec![player_id],
+        });
+    }
+
+    pub fn tile_effects_at(&self, pos: MapPos) -> Vec<&TileEffect> {
+        self.tile_effects.iter().filter(|e| e.pos == pos).collect()
+    }
+
+    pub fn get_move_cost(&self, pos: MapPos) -> MInt {
+        let mut cost = 1;
+        for effect in self.tile_effects_at(pos).iter() {
+            cost += effect.kind.move_cost();
+        }
+        cost
+    }
+
+    pub fn is_blocking_los(&self, pos: MapPos) -> bool {
+        self.tile_effects_at(pos).iter().any(|e| e.kind.is_blocking_los())
+    }
+
+    fn update_tile_effects(&mut self) {
+        for effect in self.tile_effects.iter_mut() {
+            effect.turns_left -= 1;
+        }
+        self.tile_effects.retain(|e| e.turns_left > 0);
+    }
+
+    pub fn get_score(&self, player_id: PlayerId) -> MInt {
+        match self.scores.find(&player_id) {
+            Some(score) => *score,
+            None => 0,
+        }
+    }
+
+    pub fn get_objective_owner(&self, id: ObjectiveId) -> Option<PlayerId> {
+        self.objective_owners.find(&id).map(|player_id| *player_id)
+    }
+
+    pub fn get_resources(&self, player_id: PlayerId) -> MInt {
+        match self.resources.find(&player_id) {
+            Some(resources) => *resources,
+            None => 0,
         }
     }
 
     pub fn units_at(&'a self, pos: MapPos) -> Vec<&'a Unit> {
         let mut units = Vec::new();
         for (_, unit) in self.units.iter() {
-            if unit.pos == pos {
+            if unit.pos == pos && !unit.is_loaded() {
                 units.push(unit);
             }
         }
+        units.sort_by(|a, b| a.id.cmp(&b.id));
         units
     }
 
+    // Checks if unit of given type can be added to stack in this tile
+    pub fn can_stack(
+        &self,
+        object_types: &ObjectTypes,
+        stack_limit: MInt,
+        pos: MapPos,
+        player_id: PlayerId,
+        type_id: UnitTypeId,
+    ) -> bool {
+        let mut size = object_types.get_unit_type(type_id).size;
+        let units = self.units_at(pos);
+        if units.len() >= MAX_UNITS_IN_STACK {
+            return false;
+        }
+        for unit in units.iter() {
+            if unit.player_id != player_id {
+                return false;
+            }
+            size += object_types.get_unit_type(unit.type_id).size;
+        }
+        size <= stack_limit
+    }
+
+    fn add_unit(
+        &mut self,
+        object_types: &ObjectTypes,
+        id: UnitId,
+        pos: MapPos,
+        type_id: UnitTypeId,
+        player_id: PlayerId,
+    ) {
+        let unit_type = object_types.get_unit_type(type_id);
+        let mut weapons = Vec::new();
+        for weapon_type_id in unit_type.weapon_type_ids.iter() {
+            let weapon_type = object_types.get_weapon_type(*weapon_type_id);
+            weapons.push(Weapon {
+                type_id: *weapon_type_id,
+                attacked: false,
+                ammo: weapon_type.max_ammo,
+                destroyed: false,
+            });
+        }
+        self.units.insert(id, Unit {
+            id: id,
+            pos: pos,
+            player_id: player_id,
+            type_id: type_id,
+            move_points: unit_type.move_points,
+            hit_points: unit_type.hit_points,
+            immobilized: false,
+            weapons: weapons,
+            suppression: 0,
+            morale_state: Steady,
+            experience: 0,
+            rank: 0,
+            passenger_ids: Vec::new(),
+            transporter_id: None,
+            mines: unit_type.mines,
+            is_fortified: false,
+            fortification: 0,
+            has_moved_or_fired: false,
+            aura: OUT_OF_COMMAND_AURA,
+        });
+    }
+
+    fn remove_unit(&mut self, unit_id: UnitId) {
+        let transporter_id = match self.units.pop(&unit_id) {
+            Some(unit) => unit.transporter_id,
+            None => panic!("No unit with id = {}", unit_id.id),
+        };
+        match transporter_id {
+            Some(transporter_id) => self.remove_passenger(transporter_id, unit_id),
+            None => {},
+        }
+    }
+
+    // Transporter may be already destroyed
+    fn remove_passenger(&mut self, transporter_id: UnitId, passenger_id: UnitId) {
+        match self.units.find_mut(&transporter_id) {
+            Some(transporter) => {
+                transporter.passenger_ids.retain(|id| *id != passenger_id);
+            },
+            None => {},
+        }
+    }
+
     fn refresh_units(&mut self, object_types: &ObjectTypes, player_id: PlayerId) {
         for (_, unit) in self.units.iter_mut() {
             if unit.player_id == player_id {
-                unit.move_points
-                    = object_types.get_unit_type(unit.type_id).move_points;
-                unit.attacked = false;
+                let unit_type = object_types.get_unit_type(unit.type_id);
+                unit.move_points = get_move_points(unit, unit_type);
+                unit.has_moved_or_fired = false;
+                for weapon in unit.weapons.iter_mut() {
+                    weapon.attacked = false;
+                }
+                if unit.is_fortified {
+                    unit.fortification = min(
+                        unit.fortification + 1, MAX_FORTIFICATION);
+                }
+            }
+        }
+    }
+
+    fn restore_unit(
+        &mut self,
+        object_types: &ObjectTypes,
+        unit_id: UnitId,
+        hit_points: MInt,
+    ) {
+        let unit = self.units.get_mut(&unit_id);
+        let max_hit_points = object_types.get_unit_type(unit.type_id).hit_points;
+        unit.hit_points = min(unit.hit_points + hit_points, max_hit_points);
+    }
+
+    // Routed leaders don't command anybody
+    fn update_auras(&mut self, object_types: &ObjectTypes) {
+        let mut leaders = Vec::new();
+        for unit in self.units.values() {
+            let unit_type = object_types.get_unit_type(unit.type_id);
+            if unit_type.is_leader() && unit.morale_state != Routed {
+                leaders.push((unit.player_id, unit.pos, unit_type.command_radius));
+            }
+        }
+        for (_, unit) in self.units.iter_mut() {
+            let is_in_command = leaders.iter().any(|&(player_id, pos, radius)| {
+                player_id == unit.player_id && distance(pos, unit.pos) <= radius
+            });
+            unit.aura = if is_in_command {
+                IN_COMMAND_AURA
+            } else {
+                OUT_OF_COMMAND_AURA
+            };
+        }
+    }
+
+    fn recover_units(&mut self, object_types: &ObjectTypes, player_id: PlayerId) {
+        for (_, unit) in self.units.iter_mut() {
+            if unit.player_id == player_id {
+                let unit_type = object_types.get_unit_type(unit.type_id);
+                recover_morale(unit, unit_type);
             }
         }
     }
@@ -47,37 +333,238 @@ impl<'a> GameState {
         match *event {
             EventMove(id, ref path) => {
                 let pos = *path.last().unwrap();
-                let unit = self.units.get_mut(&id);
-                unit.pos = pos;
-                assert!(unit.move_points > 0);
-                unit.move_points = 0;
+                let passenger_ids = {
+                    let unit = self.units.get_mut(&id);
+                    unit.pos = pos;
+                    assert!(unit.move_points > 0);
+                    unit.move_points = 0;
+                    unit.has_moved_or_fired = true;
+                    unit.is_fortified = false;
+                    unit.fortification = 0;
+                    unit.passenger_ids.clone()
+                };
+                for passenger_id in passenger_ids.iter() {
+                    self.units.get_mut(passenger_id).pos = pos;
+                }
             },
-            EventEndTurn(_, new_player_id) => {
+            EventEndTurn(old_player_id, new_player_id) => {
+                self.recover_units(object_types, old_player_id);
                 self.refresh_units(object_types, new_player_id);
+                self.update_tile_effects();
             },
-            EventCreateUnit(id, pos, type_id, player_id) => {
-                assert!(self.units.find(&id).is_none());
-                let move_points
-                    = object_types.get_unit_type(type_id).move_points;
-                self.units.insert(id, Unit {
-                    id: id,
+            EventLayMines(unit_id, pos) => {
+                let player_id = {
+                    let unit = self.units.get_mut(&unit_id);
+                    assert!(unit.mines > 0);
+                    unit.mines -= 1;
+                    unit.move_points = 0;
+                    unit.player_id
+                };
+                self.reveal_minefield(pos, player_id);
+            },
+            EventClearMines(unit_id, pos) => {
+                self.units.get_mut(&unit_id).move_points = 0;
+                self.minefields.retain(|m| m.pos != pos);
+            },
+            EventRevealMinefield(pos, player_id) => {
+                self.reveal_minefield(pos, player_id);
+            },
+            EventHealUnit(healer_id, unit_id, hit_points) => {
+                self.units.get_mut(&healer_id).move_points = 0;
+                self.restore_unit(object_types, unit_id, hit_points);
+            },
+            EventRestoreUnit(unit_id, hit_points) => {
+                self.restore_unit(object_types, unit_id, hit_points);
+            },
+            EventRepairVehicle(unit_id, damage) => {
+                let unit = self.units.get_mut(&unit_id);
+                match damage {
+                    Immobilized => unit.immobilized = false,
+                    WeaponDestroyed(weapon_index) => {
+                        unit.weapons.get_mut(weapon_index as uint).destroyed = false;
+                    },
+                }
+            },
+            EventFortify(unit_id) => {
+                let unit = self.units.get_mut(&unit_id);
+                unit.is_fortified = true;
+                unit.fortification = max(unit.fortification, 1);
+                unit.move_points = 0;
+                for weapon in unit.weapons.iter_mut() {
+                    weapon.attacked = true;
+                }
+            },
+            EventMineExplosion(unit_id, damage) => {
+                let killed = {
+                    let unit = self.units.get_mut(&unit_id);
+                    unit.hit_points -= damage;
+                    unit.hit_points <= 0
+                };
+                if killed {
+                    self.remove_unit(unit_id);
+                }
+            },
+            EventCreateTileEffect(pos, kind) => {
+                self.tile_effects.push(TileEffect {
                     pos: pos,
-                    player_id: player_id,
-                    type_id: type_id,
-                    move_points: move_points,
-                    attacked: false,
+                    kind: kind,
+                    turns_left: kind.lifetime(),
                 });
             },
-            EventAttackUnit(attacker_id, defender_id, killed) => {
+            EventCreateUnit(id, pos, type_id, player_id) => {
+                assert!(self.units.find(&id).is_none());
+                self.add_unit(object_types, id, pos, type_id, player_id);
+            },
+            EventRevealUnit(id, pos, type_id, player_id, experience, rank) => {
+                if self.units.find(&id).is_none() {
+                    self.add_unit(object_types, id, pos, type_id, player_id);
+                    let unit = self.units.get_mut(&id);
+                    unit.experience = experience;
+                    unit.rank = rank;
+                }
+            },
+            EventAttackUnit(attacker_id, defender_id, weapon_index, damage) => {
+                let killed = {
+                    let defender = self.units.get_mut(&defender_id);
+                    defender.hit_points -= damage;
+                    defender.hit_points <= 0
+                };
                 if killed {
-                    assert!(self.units.find(&defender_id).is_some());
-                    self.units.remove(&defender_id);
+                    self.remove_unit(defender_id);
                 }
                 let unit = self.units.get_mut(&attacker_id);
-                assert!(!unit.attacked);
-                unit.attacked = true;
+                unit.experience += if killed {
+                    EXPERIENCE_FOR_KILL
+                } else {
+                    EXPERIENCE_FOR_ATTACK
+                };
+                let weapon = unit.weapons.get_mut(weapon_index as uint);
+                assert!(!weapon.attacked);
+                assert!(weapon.ammo > 0);
+                weapon.attacked = true;
+                weapon.ammo -= 1;
+                unit.has_moved_or_fired = true;
+            },
+            EventIndirectAttack(attacker_id, weapon_index, _, ref hits) => {
+                let attacker_player_id = {
+                    let unit = self.units.get_mut(&attacker_id);
+                    unit.experience += EXPERIENCE_FOR_ATTACK;
+                    unit.has_moved_or_fired = true;
+                    let weapon = unit.weapons.get_mut(weapon_index as uint);
+                    assert!(!weapon.attacked);
+                    assert!(weapon.ammo > 0);
+                    weapon.attacked = true;
+                    weapon.ammo -= 1;
+                    unit.player_id
+                };
+                let mut kills = 0;
+                for hit in hits.iter() {
+                    let (killed, player_id) = {
+                        let unit = self.units.get_mut(&hit.unit_id);
+                        unit.hit_points -= hit.damage;
+                        (unit.hit_points <= 0, unit.player_id)
+                    };
+                    if killed {
+                        self.remove_unit(hit.unit_id);
+                        if player_id != attacker_player_id {
+                            kills += 1;
+                        }
+                    }
+                }
+                match self.units.find_mut(&attacker_id) {
+                    Some(unit) => unit.experience += kills * EXPERIENCE_FOR_KILL,
+                    None => {},
+                }
+            },
+            EventChangeResources(player_id, diff) => {
+                let resources = self.get_resources(player_id) + diff;
+                assert!(resources >= 0);
+                self.resources.insert(player_id, resources);
+            },
+            EventCaptureObjective(objective_id, player_id) => {
+                self.objective_owners.insert(objective_id, player_id);
+            },
+            EventChangeScore(player_id, diff) => {
+                let score = self.get_score(player_id) + diff;
+                self.scores.insert(player_id, score);
+            },
+            EventVictory(_) => {},
+            EventDraw => {},
+            EventOrdersDone(_) => {},
+            EventUndoMove(ref info) => {
+                let pos = info.path[0];
+                let passenger_ids = {
+                    let unit = self.units.get_mut(&info.unit_id);
+                    unit.pos = pos;
+                    unit.move_points = info.move_points;
+                    unit.is_fortified = info.is_fortified;
+                    unit.fortification = info.fortification;
+                    unit.has_moved_or_fired = info.has_moved_or_fired;
+                    unit.passenger_ids.clone()
+                };
+                for passenger_id in passenger_ids.iter() {
+                    self.units.get_mut(passenger_id).pos = pos;
+                }
+            },
+            EventPlayerReady(_) => {},
+            EventShowMessage(_) => {},
+            EventChangeObjective(_, _, _) => {},
+            EventGainExperience(unit_id, experience) => {
+                self.units.get_mut(&unit_id).experience += experience;
+            },
+            EventSuppressUnit(unit_id, suppression, morale_state) => {
+                let unit = self.units.get_mut(&unit_id);
+                unit.suppression += suppression;
+                unit.morale_state = morale_state;
+            },
+            EventPromoteUnit(unit_id, rank) => {
+                let unit = self.units.get_mut(&unit_id);
+                assert!(rank > unit.rank);
+                unit.rank = rank;
+            },
+            EventLoadUnit(passenger_id, transporter_id) => {
+                let pos = {
+                    let transporter = self.units.get_mut(&transporter_id);
+                    transporter.passenger_ids.push(passenger_id);
+                    transporter.pos
+                };
+                let passenger = self.units.get_mut(&passenger_id);
+                assert!(!passenger.is_loaded());
+                passenger.transporter_id = Some(transporter_id);
+                passenger.pos = pos;
+                passenger.move_points = 0;
+                passenger.is_fortified = false;
+                passenger.fortification = 0;
+            },
+            EventUnloadUnit(passenger_id, pos) => {
+                let transporter_id = {
+                    let passenger = self.units.get_mut(&passenger_id);
+                    let transporter_id = passenger.transporter_id.unwrap();
+                    passenger.transporter_id = None;
+                    passenger.pos = pos;
+                    passenger.move_points = 0;
+                    passenger.has_moved_or_fired = true;
+                    transporter_id
+                };
+                self.remove_passenger(transporter_id, passenger_id);
+            },
+            EventDestroyUnit(unit_id) => {
+                self.remove_unit(unit_id);
+            },
+            EventDamageVehicle(unit_id, damage) => {
+                let unit = self.units.get_mut(&unit_id);
+                match damage {
+                    Immobilized => {
+                        unit.immobilized = true;
+                        unit.move_points = 0;
+                    },
+                    WeaponDestroyed(weapon_index) => {
+                        unit.weapons.get_mut(weapon_index as uint).destroyed = true;
+                    },
+                }
             },
         }
+        self.update_auras(object_types);
     }
 }
 
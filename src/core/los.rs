@@ -0,0 +1,66 @@
+// See LICENSE file for copyright and license details.
+
+use std::num::{abs};
+use cgmath::{Vector2};
+use core::types::{MInt, MapPos};
+use core::map::{distance};
+use core::game_state::GameState;
+
+// Cube coordinates that match core::map::distance
+fn to_cube(pos: MapPos) -> (f32, f32, f32) {
+    let x = (pos.v.x + pos.v.y / 2) as f32;
+    let y = -pos.v.y as f32;
+    (x, y, -x - y)
+}
+
+fn from_cube(x: MInt, y: MInt) -> MapPos {
+    let row = -y;
+    MapPos{v: Vector2{x: x - row / 2, y: row}}
+}
+
+fn round_cube(x: f32, y: f32, z: f32) -> (MInt, MInt) {
+    let mut rx = x.round();
+    let mut ry = y.round();
+    let rz = z.round();
+    let dx = abs(rx - x);
+    let dy = abs(ry - y);
+    let dz = abs(rz - z);
+    if dx > dy && dx > dz {
+        rx = -ry - rz;
+    } else if dy > dz {
+        ry = -rx - rz;
+    }
+    (rx as MInt, ry as MInt)
+}
+
+// Tiles between 'from' and 'to' (both excluded)
+fn get_line(from: MapPos, to: MapPos) -> Vec<MapPos> {
+    let n = distance(from, to);
+    let (ax, ay, az) = to_cube(from);
+    let (bx, by, bz) = to_cube(to);
+    let mut line = Vec::new();
+    for i in range(1, n) {
+        // tiny shift prevents rounding ties on tile borders
+        let t = i as f32 / n as f32;
+        let (x, y) = round_cube(
+            ax + (bx - ax) * t + 1e-6,
+            ay + (by - ay) * t + 1e-6,
+            az + (bz - az) * t - 2e-6,
+        );
+        line.push(from_cube(x, y));
+    }
+    line
+}
+
+// Smoke and wrecks block line of sight, but neighbour tiles are always visible
+pub fn is_los_clear(state: &GameState, from: MapPos, to: MapPos) -> bool {
+    if distance(from, to) <= 1 {
+        return true;
+    }
+    if state.is_blocking_los(to) {
+        return false;
+    }
+    !get_line(from, to).iter().any(|pos| state.is_blocking_los(*pos))
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
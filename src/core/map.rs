@@ -42,4 +42,13 @@ pub fn distance(from: MapPos, to: MapPos) -> MInt {
     (abs(dx) + abs(dy) + abs(dx - dy)) / 2
 }
 
+// Keeps relative position of 'pos' when 'from' moves to 'to'
+pub fn shift_pos(pos: MapPos, from: MapPos, to: MapPos) -> MapPos {
+    let dx = (to.v.x + to.v.y / 2) - (from.v.x + from.v.y / 2);
+    let dy = to.v.y - from.v.y;
+    let y = pos.v.y + dy;
+    let x = (pos.v.x + pos.v.y / 2) + dx - y / 2;
+    MapPos{v: Vector2{x: x, y: y}}
+}
+
 // vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
@@ -9,5 +9,9 @@ pub mod game_state;
 pub mod core;
 pub mod conf;
 pub mod fs;
+pub mod scenario;
+pub mod rules;
+pub mod los;
+pub mod campaign;
 
 // vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
@@ -75,15 +75,20 @@ impl Pathfinder {
         &mut self,
         state: &GameState,
         unit: &Unit,
+        max_move_cost: MInt,
         original_pos: MapPos,
         neighbour_pos: MapPos
     ) {
         let old_cost = self.map.tile(original_pos).cost;
         let tile = self.map.tile_mut(neighbour_pos);
-        let new_cost = old_cost + 1;
-        let units_count = state.units_at(neighbour_pos).len();
-        if tile.cost > new_cost && units_count == 0
-            && new_cost <= unit.move_points
+        let new_cost = old_cost + state.get_move_cost(neighbour_pos);
+        // Units can pass through tiles with friendly units
+        // and avoid minefields that their player knows about
+        let is_blocked = state.units_at(neighbour_pos).iter()
+            .any(|u| u.player_id != unit.player_id)
+            || state.is_minefield_known(unit.player_id, neighbour_pos);
+        if tile.cost > new_cost && !is_blocked
+            && new_cost <= max_move_cost
         {
             self.queue.push(neighbour_pos);
             tile.cost = new_cost;
@@ -103,6 +108,7 @@ impl Pathfinder {
         &mut self,
         state: &GameState,
         unit: &Unit,
+        max_move_cost: MInt,
         pos: MapPos
     ) {
         assert!(self.map.is_inboard(pos));
@@ -110,7 +116,7 @@ impl Pathfinder {
             let neighbour_pos = Dir::get_neighbour_pos(pos, Dir::from_int(i as MInt));
             if self.map.is_inboard(neighbour_pos) {
                 self.process_neighbour_pos(
-                    state, unit, pos, neighbour_pos);
+                    state, unit, max_move_cost, pos, neighbour_pos);
             }
         }
     }
@@ -122,16 +128,34 @@ impl Pathfinder {
         start_tile.parent = None;
     }
 
-    pub fn fill_map(&mut self, state: &GameState, unit: &Unit) {
+    fn fill_map_with_limit(
+        &mut self,
+        state: &GameState,
+        unit: &Unit,
+        max_move_cost: MInt,
+    ) {
         assert!(self.queue.len() == 0);
         self.clean_map();
         self.push_start_pos_to_queue(unit.pos);
         while self.queue.len() != 0 {
             let pos = self.queue.remove(0).unwrap();
-            self.try_to_push_neighbours(state, unit, pos);
+            self.try_to_push_neighbours(state, unit, max_move_cost, pos);
         }
     }
 
+    pub fn fill_map(&mut self, state: &GameState, unit: &Unit) {
+        self.fill_map_with_limit(state, unit, unit.move_points);
+    }
+
+    // Ignores move points, used for multi-turn moves
+    pub fn fill_map_unlimited(&mut self, state: &GameState, unit: &Unit) {
+        self.fill_map_with_limit(state, unit, max_cost() - 1);
+    }
+
+    pub fn is_reachable(&self, pos: MapPos) -> bool {
+        self.map.tile(pos).cost != max_cost()
+    }
+
     pub fn get_path(&self, destination: MapPos) -> Vec<MapPos> {
         let mut path = Vec::new();
         let mut pos = destination;
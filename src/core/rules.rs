@@ -0,0 +1,66 @@
+// See LICENSE file for copyright and license details.
+
+use std::rand::{task_rng, Rng};
+use std::cmp::{max, min};
+use core::types::{MInt};
+use core::conf::Config;
+
+// Constants of combat formulas.
+// Every test rolls a die in [die_min, die_max) and passes
+// if the roll is less than the needed value.
+#[deriving(Decodable, Clone)]
+pub struct Rules {
+    pub hit_base: MInt, // hit = hit_base + size + accuracy + weapon_skill
+    pub pierce_base: MInt, // pierce = pierce_base - armor + ap
+    pub wound_base: MInt, // wound = wound_base - toughness + damage
+    // shell lands on target if scatter_base + accuracy + weapon_skill
+    // + rank - suppression / 2 passes, otherwise in neighbour tile
+    pub scatter_base: MInt,
+    pub die_min: MInt,
+    pub die_max: MInt,
+}
+
+impl Rules {
+    pub fn new() -> Rules {
+        Rules {
+            hit_base: -15,
+            pierce_base: 5,
+            wound_base: 0,
+            scatter_base: -4,
+            die_min: -5,
+            die_max: 5,
+        }
+    }
+
+    // Uses default rules if config has no "rules" section
+    pub fn from_config(config: &Config) -> Rules {
+        let rules = match config.get_opt("rules") {
+            Some(rules) => rules,
+            None => Rules::new(),
+        };
+        rules.check();
+        rules
+    }
+
+    // Die range can't be empty
+    pub fn check(&self) {
+        if self.die_min >= self.die_max {
+            panic!("Bad rules: die_min ({}) must be less than die_max ({})",
+                self.die_min, self.die_max);
+        }
+    }
+
+    pub fn test(&self, needed: MInt) -> bool {
+        let real = task_rng().gen_range(self.die_min, self.die_max);
+        real < needed
+    }
+
+    // Exact probability of passing test(needed)
+    pub fn test_probability(&self, needed: MInt) -> f32 {
+        let range = self.die_max - self.die_min;
+        let passed = min(max(needed - self.die_min, 0), range);
+        passed as f32 / range as f32
+    }
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
@@ -0,0 +1,287 @@
+// See LICENSE file for copyright and license details.
+
+use std::collections::hashmap::HashMap;
+use cgmath::{Vector2};
+use core::types::{Size2, MInt, PlayerId, MapPos, ObjectiveId};
+use core::map::{MapPosIter, distance};
+use core::rules::Rules;
+
+// Factories also work as workshops that repair vehicles
+pub struct Factory {
+    pub pos: MapPos,
+    pub player_id: PlayerId,
+}
+
+pub struct ScenarioMinefield {
+    pub pos: MapPos,
+    pub player_id: PlayerId, // only this player knows about it
+}
+
+// Units that arrive at the start of owner's turn
+pub struct Reinforcement {
+    pub player_id: PlayerId,
+    pub round: MInt,
+    pub unit_type_names: Vec<String>,
+    pub entry_positions: Vec<MapPos>, // occupied ones are replaced by nearest free tiles
+}
+
+pub enum TriggerCondition {
+    ConditionRound(MInt), // round N is reached
+    ConditionUnitInArea(PlayerId, Vec<MapPos>),
+    ConditionUnitTypeDestroyed(PlayerId, String), // owner, unit type name
+    ConditionObjectiveCaptured(ObjectiveId, PlayerId),
+}
+
+#[deriving(Clone)]
+pub enum TriggerAction {
+    ActionSpawnUnits(PlayerId, Vec<String>, Vec<MapPos>), // names, entry tiles
+    ActionShowMessage(String),
+    ActionChangeObjective(ObjectiveId, /* score: */ MInt, /* income: */ MInt),
+    ActionGrantResources(PlayerId, MInt),
+    ActionEndGame(PlayerId), // winner
+}
+
+// Actions are done once when all conditions are true
+pub struct Trigger {
+    pub conditions: Vec<TriggerCondition>,
+    pub actions: Vec<TriggerAction>,
+}
+
+pub struct Objective {
+    pub pos: MapPos,
+    pub score: MInt, // per turn
+    pub income: MInt,
+}
+
+pub struct Scenario {
+    pub deploy_zones: HashMap<PlayerId, Vec<MapPos>>,
+    pub rosters: HashMap<PlayerId, Vec<String>>, // unit type names to deploy
+    pub factories: Vec<Factory>,
+    pub objectives: Vec<Objective>,
+    pub minefields: Vec<ScenarioMinefield>,
+    pub reinforcements: Vec<Reinforcement>,
+    pub triggers: Vec<Trigger>,
+    pub start_resources: MInt,
+    pub income: MInt,
+    pub victory_score: MInt,
+    pub round_limit: MInt, // winner is decided by score after this round
+    pub stack_limit: MInt, // max summary size of units in one tile
+    pub repair_rate: MInt, // hit points per turn restored in workshops
+    pub rules: Option<Rules>, // overrides rules from core config
+}
+
+fn get_deploy_zone(map_size: Size2<MInt>, x: MInt) -> Vec<MapPos> {
+    let mut zone = Vec::new();
+    for pos in MapPosIter::new(map_size) {
+        if pos.v.x == x {
+            zone.push(pos);
+        }
+    }
+    zone
+}
+
+impl Scenario {
+    // TODO: read from scenario.json
+    pub fn new(map_size: Size2<MInt>) -> Scenario {
+        let mut deploy_zones = HashMap::new();
+        deploy_zones.insert(PlayerId{id: 0}, get_deploy_zone(map_size, 0));
+        deploy_zones.insert(
+            PlayerId{id: 1}, get_deploy_zone(map_size, map_size.w - 1));
+        let mut rosters = HashMap::new();
+        for id in range(0, 2) {
+            rosters.insert(PlayerId{id: id}, vec![
+                "tank".to_string(),
+                "soldier".to_string(),
+                "officer".to_string(),
+            ]);
+        }
+        let middle_x = map_size.w / 2;
+        let middle_y = map_size.h / 2;
+        let mut triggers = Vec::new();
+        for id in range(0, 2) {
+            let player_id = PlayerId{id: id};
+            triggers.push(Trigger {
+                conditions: vec![
+                    ConditionObjectiveCaptured(ObjectiveId{id: 0}, player_id),
+                ],
+                actions: vec![
+                    ActionShowMessage(format!(
+                        "player {} captured the crossroads", id)),
+                    ActionGrantResources(player_id, 5),
+                ],
+            });
+        }
+        triggers.push(Trigger {
+            conditions: vec![ConditionRound(6)],
+            actions: vec![
+                ActionShowMessage("crossroads became more important".to_string()),
+                ActionChangeObjective(ObjectiveId{id: 0}, 4, 3),
+            ],
+        });
+        Scenario {
+            deploy_zones: deploy_zones,
+            rosters: rosters,
+            factories: vec![
+                Factory {
+                    pos: MapPos{v: Vector2{x: 1, y: middle_y}},
+                    player_id: PlayerId{id: 0},
+                },
+                Factory {
+                    pos: MapPos{v: Vector2{x: map_size.w - 2, y: middle_y}},
+                    player_id: PlayerId{id: 1},
+                },
+            ],
+            objectives: vec![
+                Objective {
+                    pos: MapPos{v: Vector2{x: middle_x, y: middle_y}},
+                    score: 2,
+                    income: 3,
+                },
+                Objective {
+                    pos: MapPos{v: Vector2{x: middle_x, y: 1}},
+                    score: 1,
+                    income: 1,
+                },
+                Objective {
+                    pos: MapPos{v: Vector2{x: middle_x, y: map_size.h - 2}},
+                    score: 1,
+                    income: 1,
+                },
+            ],
+            minefields: vec![
+                ScenarioMinefield {
+                    pos: MapPos{v: Vector2{x: middle_x - 2, y: middle_y}},
+                    player_id: PlayerId{id: 0},
+                },
+                ScenarioMinefield {
+                    pos: MapPos{v: Vector2{x: middle_x + 2, y: middle_y}},
+                    player_id: PlayerId{id: 1},
+                },
+            ],
+            reinforcements: vec![
+                Reinforcement {
+                    player_id: PlayerId{id: 0},
+                    round: 3,
+                    unit_type_names: vec!["soldier".to_string()],
+                    entry_positions: vec![MapPos{v: Vector2{x: 0, y: middle_y}}],
+                },
+                Reinforcement {
+                    player_id: PlayerId{id: 1},
+                    round: 3,
+                    unit_type_names: vec!["soldier".to_string()],
+                    entry_positions: vec![
+                        MapPos{v: Vector2{x: map_size.w - 1, y: middle_y}},
+                    ],
+                },
+            ],
+            triggers: triggers,
+            start_resources: 10,
+            income: 5,
+            victory_score: 20,
+            round_limit: 15,
+            stack_limit: 10,
+            repair_rate: 1,
+            rules: None,
+        }
+    }
+
+    // Campaign missions are variations of the default scenario
+    pub fn from_name(name: &str, map_size: Size2<MInt>) -> Scenario {
+        let mut scenario = Scenario::new(map_size);
+        let middle_y = map_size.h / 2;
+        let enemy_entry_pos = MapPos{v: Vector2{x: map_size.w - 1, y: middle_y}};
+        match name {
+            "skirmish" => {},
+            "border" => {
+                scenario.reinforcements.clear();
+                scenario.victory_score = 12;
+                scenario.round_limit = 10;
+            },
+            "crossroads" => {
+                let crossroads = scenario.objectives.get_mut(0);
+                crossroads.score = 3;
+                crossroads.income = 4;
+                scenario.reinforcements.push(Reinforcement {
+                    player_id: PlayerId{id: 1},
+                    round: 5,
+                    unit_type_names: vec!["tank".to_string()],
+                    entry_positions: vec![enemy_entry_pos],
+                });
+            },
+            "last stand" => {
+                let player_id = PlayerId{id: 0};
+                for round in [3, 6].iter() {
+                    scenario.reinforcements.push(Reinforcement {
+                        player_id: PlayerId{id: 1},
+                        round: *round,
+                        unit_type_names: vec![
+                            "soldier".to_string(),
+                            "soldier".to_string(),
+                        ],
+                        entry_positions: vec![enemy_entry_pos],
+                    });
+                }
+                scenario.triggers.push(Trigger {
+                    conditions: vec![
+                        ConditionRound(10),
+                        ConditionObjectiveCaptured(ObjectiveId{id: 0}, player_id),
+                    ],
+                    actions: vec![
+                        ActionShowMessage("the crossroads held out".to_string()),
+                        ActionEndGame(player_id),
+                    ],
+                });
+                scenario.round_limit = 12;
+                // TODO: read from scenario file
+                // Night battle: everybody hits worse
+                let mut rules = Rules::new();
+                rules.hit_base -= 2;
+                scenario.rules = Some(rules);
+            },
+            _ => panic!("Unknown scenario: \"{}\"", name),
+        }
+        scenario
+    }
+
+    pub fn get_objective_ids(&self) -> Vec<ObjectiveId> {
+        let mut ids = Vec::new();
+        for id in range(0, self.objectives.len()) {
+            ids.push(ObjectiveId{id: id as MInt});
+        }
+        ids
+    }
+
+    pub fn get_objective<'a>(&'a self, id: ObjectiveId) -> &'a Objective {
+        &self.objectives[id.id as uint]
+    }
+
+    pub fn is_in_deploy_zone(&self, player_id: PlayerId, pos: MapPos) -> bool {
+        match self.deploy_zones.find(&player_id) {
+            Some(zone) => zone.iter().any(|p| *p == pos),
+            None => false,
+        }
+    }
+
+    fn is_near_factory(&self, player_id: PlayerId, pos: MapPos) -> bool {
+        for factory in self.factories.iter() {
+            if factory.player_id == player_id
+                && distance(factory.pos, pos) == 1
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn is_deploy_pos(&self, player_id: PlayerId, pos: MapPos) -> bool {
+        self.is_in_deploy_zone(player_id, pos)
+            || self.is_near_factory(player_id, pos)
+    }
+
+    pub fn is_workshop_pos(&self, player_id: PlayerId, pos: MapPos) -> bool {
+        self.is_near_factory(player_id, pos) || self.factories.iter()
+            .any(|f| f.player_id == player_id && f.pos == pos)
+    }
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
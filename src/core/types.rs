@@ -17,6 +17,9 @@ pub struct PlayerId{pub id: MInt}
 #[deriving(PartialOrd, Ord, PartialEq, Eq, Hash, Clone)]
 pub struct UnitId{pub id: MInt}
 
+#[deriving(PartialEq, Eq, Hash, Clone)]
+pub struct ObjectiveId{pub id: MInt}
+
 #[deriving(PartialEq, Clone, Show)]
 pub struct MapPos{pub v: Vector2<MInt>}
 
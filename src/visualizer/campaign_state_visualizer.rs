@@ -0,0 +1,175 @@
+// See LICENSE file for copyright and license details.
+
+use glfw;
+use cgmath::{Vector2};
+use core::types::{MInt};
+use core::fs::FileSystem;
+use core::campaign::{
+    Campaign,
+    CampaignProgress,
+    get_save_path,
+    get_campaign_path,
+};
+use visualizer::mgl;
+use visualizer::types::{Time, ScreenPos};
+use visualizer::gui::{ButtonManager, Button, ButtonId};
+use visualizer::context::Context;
+use visualizer::state_visualizer::{
+    StateVisualizer,
+    StateChangeCommand,
+    StartCampaignMission,
+    CloseCampaign,
+};
+
+pub struct CampaignStateVisualizer {
+    save_path: Path,
+    campaign: Campaign,
+    progress: CampaignProgress,
+    button_manager: ButtonManager,
+    button_play_id: Option<ButtonId>, // None if campaign is finished
+    button_new_campaign_id: ButtonId,
+    button_back_id: ButtonId,
+    is_mission_started: bool,
+    commands_rx: Receiver<StateChangeCommand>,
+    commands_tx: Sender<StateChangeCommand>,
+}
+
+impl CampaignStateVisualizer {
+    pub fn new(fs: &FileSystem, context: &Context) -> CampaignStateVisualizer {
+        let save_path = get_save_path(fs);
+        let progress = CampaignProgress::load(&save_path);
+        let (commands_tx, commands_rx) = channel();
+        let mut vis = CampaignStateVisualizer {
+            save_path: save_path,
+            campaign: Campaign::load(&get_campaign_path(fs)),
+            progress: progress,
+            button_manager: ButtonManager::new(),
+            button_play_id: None,
+            button_new_campaign_id: ButtonId{id: 0},
+            button_back_id: ButtonId{id: 0},
+            is_mission_started: false,
+            commands_rx: commands_rx,
+            commands_tx: commands_tx,
+        };
+        vis.update_buttons(context);
+        vis
+    }
+
+    fn get_info_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        lines.push(match self.progress.mission_index {
+            Some(index) => format!("next mission: {}",
+                self.campaign.get_mission(index).name),
+            None => "campaign finished".to_string(),
+        });
+        lines.push(format!("victories: {}", self.progress.victories));
+        lines.push(format!("resources: {}", self.progress.resources));
+        for unit in self.progress.units.iter() {
+            lines.push(format!("{}: experience {}",
+                unit.type_name, unit.experience));
+        }
+        lines
+    }
+
+    fn add_button(&mut self, context: &Context, label: &str, y: MInt) -> ButtonId {
+        self.button_manager.add_button(Button::new(
+            label,
+            context.font_stash.borrow_mut().deref_mut(),
+            &context.shader,
+            ScreenPos{v: Vector2{x: 10, y: y}})
+        )
+    }
+
+    fn update_buttons(&mut self, context: &Context) {
+        self.button_manager = ButtonManager::new();
+        self.button_back_id = self.add_button(context, "back", 10);
+        self.button_new_campaign_id = self.add_button(
+            context, "new campaign", 40);
+        self.button_play_id = if self.progress.is_finished() {
+            None
+        } else {
+            Some(self.add_button(context, "play", 70))
+        };
+        let mut y = context.win_size.h - 40;
+        for line in self.get_info_lines().iter() {
+            let _ = self.add_button(context, line.as_slice(), y);
+            y -= 30;
+        }
+    }
+
+    fn start_mission(&mut self) {
+        if self.progress.is_finished() {
+            return;
+        }
+        self.is_mission_started = true;
+        self.commands_tx.send(StartCampaignMission);
+    }
+
+    fn start_new_campaign(&mut self, context: &Context) {
+        self.progress = CampaignProgress::new();
+        self.progress.save(&self.save_path);
+        self.update_buttons(context);
+    }
+
+    fn handle_mouse_button_event(&mut self, context: &Context) {
+        let button_id = match self.button_manager.get_clicked_button_id(context) {
+            Some(button_id) => button_id,
+            None => return,
+        };
+        if Some(button_id) == self.button_play_id {
+            self.start_mission();
+        } else if button_id == self.button_new_campaign_id {
+            self.start_new_campaign(context);
+        } else if button_id == self.button_back_id {
+            self.commands_tx.send(CloseCampaign);
+        }
+    }
+}
+
+impl StateVisualizer for CampaignStateVisualizer {
+    // Progress is saved by finished mission, so reload it
+    fn logic(&mut self, context: &Context) {
+        if self.is_mission_started {
+            self.is_mission_started = false;
+            self.progress = CampaignProgress::load(&self.save_path);
+            self.update_buttons(context);
+        }
+    }
+
+    fn draw(&mut self, context: &Context, _: Time) {
+        use glfw::Context;
+        mgl::set_clear_color(mgl::BLACK_3);
+        mgl::clear_screen();
+        context.shader.activate();
+        context.shader.uniform_color(context.basic_color_id, mgl::WHITE);
+        self.button_manager.draw(context);
+        context.win.swap_buffers();
+    }
+
+    fn handle_event(&mut self, context: &Context, event: glfw::WindowEvent) {
+        match event {
+            glfw::KeyEvent(key, _, glfw::Press, _) => {
+                match key {
+                    glfw::Key1 => self.start_mission(),
+                    glfw::KeyEscape | glfw::KeyQ => {
+                        self.commands_tx.send(CloseCampaign);
+                    },
+                    _ => {},
+                }
+            },
+            glfw::MouseButtonEvent(glfw::MouseButtonLeft, glfw::Press, _) => {
+                self.handle_mouse_button_event(context);
+            },
+            _ => {},
+        }
+    }
+
+    fn get_command(&self) -> Option<StateChangeCommand> {
+        match self.commands_rx.try_recv() {
+            Ok(cmd) => Some(cmd),
+            Err(_) => None,
+        }
+    }
+}
+
+// vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
@@ -4,7 +4,7 @@ use std::rand;
 use std::rand::Rng;
 use cgmath::{Vector3, Vector, EuclideanVector};
 use visualizer::geom;
-use core::types::{MapPos, UnitId};
+use core::types::{MInt, MapPos, UnitId, ObjectiveId};
 use core::game_state::GameState;
 use core::core;
 use visualizer::mesh::{MeshId};
@@ -13,6 +13,13 @@ use visualizer::scene::{
     SceneNode,
     NodeId,
     MIN_MARKER_NODE_ID,
+    MIN_OBJECTIVE_NODE_ID,
+    MIN_TILE_EFFECT_NODE_ID,
+    MAX_TILE_EFFECT_NODE_ID,
+    MIN_MINEFIELD_NODE_ID,
+    MAX_MINEFIELD_NODE_ID,
+    MIN_FORTIFICATION_NODE_ID,
+    MAX_FORTIFICATION_NODE_ID,
     SHELL_NODE_ID,
 };
 use visualizer::types::{MFloat, WorldPos, Time};
@@ -26,6 +33,15 @@ fn marker_id(unit_id: UnitId) -> NodeId {
     NodeId{id: MIN_MARKER_NODE_ID.id + unit_id.id}
 }
 
+fn remove_unit_nodes(scene: &mut Scene, unit_id: UnitId) {
+    scene.nodes.remove(&unit_id_to_node_id(unit_id));
+    scene.nodes.remove(&marker_id(unit_id));
+}
+
+fn objective_id_to_node_id(objective_id: ObjectiveId) -> NodeId {
+    NodeId{id: MIN_OBJECTIVE_NODE_ID.id + objective_id.id}
+}
+
 pub trait EventVisualizer {
     fn is_finished(&self) -> bool;
     fn draw(&mut self, scene: &mut Scene, dtime: Time);
@@ -141,6 +157,25 @@ impl EventVisualizer for EventEndTurnVisualizer {
     fn end(&mut self, _: &mut Scene, _: &GameState) {}
 }
 
+// For events that have no visual representation
+pub struct EventSilentVisualizer;
+
+impl EventSilentVisualizer {
+    pub fn new() -> Box<EventVisualizer+'static> {
+        box EventSilentVisualizer as Box<EventVisualizer>
+    }
+}
+
+impl EventVisualizer for EventSilentVisualizer {
+    fn is_finished(&self) -> bool {
+        true
+    }
+
+    fn draw(&mut self, _: &mut Scene, _: Time) {}
+
+    fn end(&mut self, _: &mut Scene, _: &GameState) {}
+}
+
 pub struct EventCreateUnitVisualizer {
     id: UnitId,
     move_helper: MoveHelper,
@@ -269,9 +304,32 @@ fn vec3_z(z: MFloat) -> Vector3<MFloat> {
     Vector3{x: 0.0, y: 0.0, z: z}
 }
 
+// Recreates all squad nodes and then removes dead ones
+pub fn reset_unit_nodes(
+    core: &core::Core,
+    scene: &mut Scene,
+    unit_id: UnitId,
+    type_id: core::UnitTypeId,
+    mesh_id: MeshId,
+    alive_nodes_count: MInt,
+) {
+    {
+        let node = scene.nodes.get_mut(&unit_id_to_node_id(unit_id));
+        node.children = get_unit_scene_nodes(core, type_id, mesh_id);
+    }
+    set_alive_nodes_count(scene, unit_id, alive_nodes_count);
+}
+
+// Infantry squads lose soldier nodes when damaged
+pub fn set_alive_nodes_count(scene: &mut Scene, unit_id: UnitId, count: MInt) {
+    let node = scene.nodes.get_mut(&unit_id_to_node_id(unit_id));
+    node.children.truncate(count as uint);
+}
+
 pub struct EventAttackUnitVisualizer {
     defender_id: UnitId,
     killed: bool,
+    alive_nodes_count: MInt,
     move_helper: MoveHelper,
     shell_move: MoveHelper,
 }
@@ -283,6 +341,7 @@ impl EventAttackUnitVisualizer {
         attacker_id: UnitId,
         defender_id: UnitId,
         killed: bool,
+        alive_nodes_count: MInt,
         shell_mesh_id: MeshId
     ) -> Box<EventVisualizer+'static> {
         let node_id = unit_id_to_node_id(defender_id);
@@ -303,6 +362,7 @@ impl EventAttackUnitVisualizer {
         box EventAttackUnitVisualizer {
             defender_id: defender_id,
             killed: killed,
+            alive_nodes_count: alive_nodes_count,
             move_helper: move_helper,
             shell_move: shell_move,
         } as Box<EventVisualizer>
@@ -330,12 +390,374 @@ impl EventVisualizer for EventAttackUnitVisualizer {
 
     fn end(&mut self, scene: &mut Scene, _: &GameState) {
         if self.killed {
-            let node_id = unit_id_to_node_id(self.defender_id);
-            scene.nodes.remove(&node_id);
-            scene.nodes.remove(&marker_id(self.defender_id));
+            remove_unit_nodes(scene, self.defender_id);
+        } else {
+            set_alive_nodes_count(
+                scene, self.defender_id, self.alive_nodes_count);
+        }
+        scene.nodes.remove(&SHELL_NODE_ID);
+    }
+}
+
+pub struct EventIndirectAttackVisualizer {
+    alive_nodes_counts: Vec<(UnitId, MInt)>, // zero for killed units
+    shell_move: MoveHelper,
+}
+
+impl EventIndirectAttackVisualizer {
+    pub fn new(
+        scene: &mut Scene,
+        _: &GameState,
+        attacker_id: UnitId,
+        impact_pos: MapPos,
+        alive_nodes_counts: Vec<(UnitId, MInt)>,
+        shell_mesh_id: MeshId
+    ) -> Box<EventVisualizer+'static> {
+        let from = scene.nodes[unit_id_to_node_id(attacker_id)].pos;
+        let to = geom::map_pos_to_world_pos(impact_pos);
+        scene.nodes.insert(SHELL_NODE_ID, SceneNode {
+            pos: from,
+            rot: 0.0,
+            mesh_id: Some(shell_mesh_id),
+            children: Vec::new(),
+        });
+        box EventIndirectAttackVisualizer {
+            alive_nodes_counts: alive_nodes_counts,
+            shell_move: MoveHelper::new(from, to, 10.0),
+        } as Box<EventVisualizer>
+    }
+}
+
+impl EventVisualizer for EventIndirectAttackVisualizer {
+    fn is_finished(&self) -> bool {
+        self.shell_move.is_finished()
+    }
+
+    fn draw(&mut self, scene: &mut Scene, dtime: Time) {
+        scene.nodes.get_mut(&SHELL_NODE_ID).pos = self.shell_move.step(dtime);
+    }
+
+    fn end(&mut self, scene: &mut Scene, _: &GameState) {
+        for &(unit_id, count) in self.alive_nodes_counts.iter() {
+            if count <= 0 {
+                remove_unit_nodes(scene, unit_id);
+            } else {
+                set_alive_nodes_count(scene, unit_id, count);
+            }
         }
         scene.nodes.remove(&SHELL_NODE_ID);
     }
 }
 
+pub struct EventCaptureObjectiveVisualizer;
+
+impl EventCaptureObjectiveVisualizer {
+    pub fn new(
+        scene: &mut Scene,
+        _: &GameState,
+        objective_id: ObjectiveId,
+        pos: MapPos,
+        marker_mesh_id: MeshId
+    ) -> Box<EventVisualizer+'static> {
+        let node_id = objective_id_to_node_id(objective_id);
+        let world_pos = geom::map_pos_to_world_pos(pos);
+        let flag_offset = geom::index_to_hex_vertex_s(0.6, 1).v;
+        scene.nodes.remove(&node_id);
+        scene.nodes.insert(node_id, SceneNode {
+            pos: WorldPos{v: world_pos.v.add_v(&flag_offset)},
+            rot: 0.0,
+            mesh_id: Some(marker_mesh_id),
+            children: Vec::new(),
+        });
+        box EventCaptureObjectiveVisualizer as Box<EventVisualizer>
+    }
+}
+
+impl EventVisualizer for EventCaptureObjectiveVisualizer {
+    fn is_finished(&self) -> bool {
+        true
+    }
+
+    fn draw(&mut self, _: &mut Scene, _: Time) {}
+
+    fn end(&mut self, _: &mut Scene, _: &GameState) {}
+}
+
+pub struct EventPromoteUnitVisualizer;
+
+fn get_rank_scene_nodes(rank: MInt, rank_mesh_id: MeshId) -> Vec<SceneNode> {
+    let mut nodes = Vec::new();
+    for i in range(0, rank) {
+        nodes.push(SceneNode {
+            pos: WorldPos{v: Vector3{x: 0.3, y: 0.0, z: 0.1 * (i as MFloat)}},
+            rot: 0.0,
+            mesh_id: Some(rank_mesh_id),
+            children: Vec::new(),
+        });
+    }
+    nodes
+}
+
+pub fn set_rank_markers(
+    scene: &mut Scene,
+    unit_id: UnitId,
+    rank: MInt,
+    rank_mesh_id: MeshId,
+) {
+    let marker_node = scene.nodes.get_mut(&marker_id(unit_id));
+    marker_node.children = get_rank_scene_nodes(rank, rank_mesh_id);
+}
+
+impl EventPromoteUnitVisualizer {
+    pub fn new(
+        scene: &mut Scene,
+        _: &GameState,
+        unit_id: UnitId,
+        rank: MInt,
+        rank_mesh_id: MeshId
+    ) -> Box<EventVisualizer+'static> {
+        set_rank_markers(scene, unit_id, rank, rank_mesh_id);
+        box EventPromoteUnitVisualizer as Box<EventVisualizer>
+    }
+}
+
+impl EventVisualizer for EventPromoteUnitVisualizer {
+    fn is_finished(&self) -> bool {
+        true
+    }
+
+    fn draw(&mut self, _: &mut Scene, _: Time) {}
+
+    fn end(&mut self, _: &mut Scene, _: &GameState) {}
+}
+
+pub struct EventLoadUnitVisualizer {
+    passenger_id: UnitId,
+    move_helper: MoveHelper,
+}
+
+impl EventLoadUnitVisualizer {
+    pub fn new(
+        scene: &mut Scene,
+        _: &GameState,
+        passenger_id: UnitId,
+        transporter_id: UnitId
+    ) -> Box<EventVisualizer+'static> {
+        let from = scene.nodes[unit_id_to_node_id(passenger_id)].pos;
+        let to = scene.nodes[unit_id_to_node_id(transporter_id)].pos;
+        scene.nodes.remove(&marker_id(passenger_id));
+        let node = scene.nodes.get_mut(&unit_id_to_node_id(passenger_id));
+        node.rot = geom::get_rot_angle(from, to);
+        box EventLoadUnitVisualizer {
+            passenger_id: passenger_id,
+            move_helper: MoveHelper::new(from, to, 1.0),
+        } as Box<EventVisualizer>
+    }
+}
+
+impl EventVisualizer for EventLoadUnitVisualizer {
+    fn is_finished(&self) -> bool {
+        self.move_helper.is_finished()
+    }
+
+    fn draw(&mut self, scene: &mut Scene, dtime: Time) {
+        let node_id = unit_id_to_node_id(self.passenger_id);
+        scene.nodes.get_mut(&node_id).pos = self.move_helper.step(dtime);
+    }
+
+    fn end(&mut self, scene: &mut Scene, _: &GameState) {
+        remove_unit_nodes(scene, self.passenger_id);
+    }
+}
+
+pub struct EventDestroyUnitVisualizer;
+
+impl EventDestroyUnitVisualizer {
+    pub fn new(
+        scene: &mut Scene,
+        _: &GameState,
+        unit_id: UnitId
+    ) -> Box<EventVisualizer+'static> {
+        // Passengers have no scene nodes
+        remove_unit_nodes(scene, unit_id);
+        box EventDestroyUnitVisualizer as Box<EventVisualizer>
+    }
+}
+
+impl EventVisualizer for EventDestroyUnitVisualizer {
+    fn is_finished(&self) -> bool {
+        true
+    }
+
+    fn draw(&mut self, _: &mut Scene, _: Time) {}
+
+    fn end(&mut self, _: &mut Scene, _: &GameState) {}
+}
+
+fn get_unit_world_pos(state: &GameState, unit: &core::Unit) -> WorldPos {
+    let stack = state.units_at(unit.pos);
+    let index = stack.iter().position(|u| u.id == unit.id).unwrap();
+    let offset = geom::get_stack_offset(stack.len() as MInt, index as MInt);
+    WorldPos{v: geom::map_pos_to_world_pos(unit.pos).v.add_v(&offset)}
+}
+
+// Places units that share one tile side by side
+pub fn update_stacks_layout(scene: &mut Scene, state: &GameState) {
+    for (_, unit) in state.units.iter() {
+        if unit.is_loaded() {
+            continue;
+        }
+        let pos = get_unit_world_pos(state, unit).v;
+        match scene.nodes.find_mut(&unit_id_to_node_id(unit.id)) {
+            Some(node) => node.pos = WorldPos{v: pos},
+            None => {},
+        }
+        match scene.nodes.find_mut(&marker_id(unit.id)) {
+            Some(node) => {
+                node.pos = WorldPos{
+                    v: pos.add_v(&vec3_z(geom::HEX_EX_RADIUS / 2.0))};
+            },
+            None => {},
+        }
+    }
+}
+
+pub struct TileEffectMeshIds {
+    pub smoke_mesh_id: MeshId,
+    pub wreck_mesh_id: MeshId,
+    pub crater_mesh_id: MeshId,
+}
+
+fn remove_nodes_in_range(scene: &mut Scene, min_id: NodeId, max_id: NodeId) {
+    let old_node_ids: Vec<NodeId> = scene.nodes.keys()
+        .filter(|id| id.id >= min_id.id && id.id <= max_id.id)
+        .map(|id| NodeId{id: id.id})
+        .collect();
+    for node_id in old_node_ids.iter() {
+        scene.nodes.remove(node_id);
+    }
+}
+
+// Recreates nodes of all tile effects, expired effects just disappear
+pub fn update_tile_effect_nodes(
+    scene: &mut Scene,
+    state: &GameState,
+    mesh_ids: &TileEffectMeshIds,
+) {
+    remove_nodes_in_range(
+        scene, MIN_TILE_EFFECT_NODE_ID, MAX_TILE_EFFECT_NODE_ID);
+    for (i, effect) in state.tile_effects.iter().enumerate() {
+        let mesh_id = match effect.kind {
+            core::Smoke => mesh_ids.smoke_mesh_id,
+            core::Wreck => mesh_ids.wreck_mesh_id,
+            core::Crater => mesh_ids.crater_mesh_id,
+        };
+        let node_id = NodeId{id: MIN_TILE_EFFECT_NODE_ID.id + i as MInt};
+        scene.nodes.insert(node_id, SceneNode {
+            pos: geom::map_pos_to_world_pos(effect.pos),
+            rot: 0.0,
+            mesh_id: Some(mesh_id),
+            children: Vec::new(),
+        });
+    }
+}
+
+// Shows only minefields that are known to the player
+pub fn update_minefield_nodes(
+    scene: &mut Scene,
+    state: &GameState,
+    mine_mesh_id: MeshId,
+) {
+    remove_nodes_in_range(scene, MIN_MINEFIELD_NODE_ID, MAX_MINEFIELD_NODE_ID);
+    for (i, minefield) in state.minefields.iter().enumerate() {
+        let node_id = NodeId{id: MIN_MINEFIELD_NODE_ID.id + i as MInt};
+        scene.nodes.insert(node_id, SceneNode {
+            pos: geom::map_pos_to_world_pos(minefield.pos),
+            rot: 0.0,
+            mesh_id: Some(mine_mesh_id),
+            children: Vec::new(),
+        });
+    }
+}
+
+pub struct EventMineExplosionVisualizer {
+    unit_id: UnitId,
+    alive_nodes_count: MInt, // zero if unit was killed
+    shell_move: MoveHelper,
+}
+
+impl EventMineExplosionVisualizer {
+    pub fn new(
+        scene: &mut Scene,
+        _: &GameState,
+        unit_id: UnitId,
+        alive_nodes_count: MInt,
+        shell_mesh_id: MeshId
+    ) -> Box<EventVisualizer+'static> {
+        let from = scene.nodes[unit_id_to_node_id(unit_id)].pos;
+        let to = WorldPos{v: from.v.add_v(&vec3_z(geom::HEX_EX_RADIUS))};
+        scene.nodes.insert(SHELL_NODE_ID, SceneNode {
+            pos: from,
+            rot: 0.0,
+            mesh_id: Some(shell_mesh_id),
+            children: Vec::new(),
+        });
+        box EventMineExplosionVisualizer {
+            unit_id: unit_id,
+            alive_nodes_count: alive_nodes_count,
+            shell_move: MoveHelper::new(from, to, 2.0),
+        } as Box<EventVisualizer>
+    }
+}
+
+impl EventVisualizer for EventMineExplosionVisualizer {
+    fn is_finished(&self) -> bool {
+        self.shell_move.is_finished()
+    }
+
+    fn draw(&mut self, scene: &mut Scene, dtime: Time) {
+        scene.nodes.get_mut(&SHELL_NODE_ID).pos = self.shell_move.step(dtime);
+    }
+
+    fn end(&mut self, scene: &mut Scene, _: &GameState) {
+        if self.alive_nodes_count <= 0 {
+            remove_unit_nodes(scene, self.unit_id);
+        } else {
+            set_alive_nodes_count(scene, self.unit_id, self.alive_nodes_count);
+        }
+        scene.nodes.remove(&SHELL_NODE_ID);
+    }
+}
+
+// Every level of fortification is shown as one more marker under the unit
+pub fn update_fortification_nodes(
+    scene: &mut Scene,
+    state: &GameState,
+    fortification_mesh_id: MeshId,
+) {
+    remove_nodes_in_range(
+        scene, MIN_FORTIFICATION_NODE_ID, MAX_FORTIFICATION_NODE_ID);
+    for (_, unit) in state.units.iter() {
+        if unit.fortification == 0 || unit.is_loaded() {
+            continue;
+        }
+        let mut children = Vec::new();
+        for i in range(0, unit.fortification) {
+            children.push(SceneNode {
+                pos: WorldPos{v: Vector3{x: 0.0, y: 0.0, z: 0.02 * (i as MFloat)}},
+                rot: 60.0 * (i as MFloat),
+                mesh_id: Some(fortification_mesh_id),
+                children: Vec::new(),
+            });
+        }
+        let node_id = NodeId{id: MIN_FORTIFICATION_NODE_ID.id + unit.id.id};
+        scene.nodes.insert(node_id, SceneNode {
+            pos: get_unit_world_pos(state, unit),
+            rot: 0.0,
+            mesh_id: None,
+            children: children,
+        });
+    }
+}
+
 // vim: set tabstop=4 shiftwidth=4 softtabstop=4 expandtab:
@@ -1,28 +1,89 @@
 // See LICENSE file for copyright and license details.
 
+use std::cmp::{min, max};
+use std::num::{abs};
 use std::collections::hashmap::HashMap;
 use time::precise_time_ns;
 use glfw;
 use cgmath::{Vector3, Vector2, Matrix4};
 use error_context;
-use core::map::{MapPosIter, distance};
+use core::map::{MapPosIter, distance, shift_pos};
 use core::types::{Size2, MInt, UnitId, PlayerId, MapPos};
 use core::game_state::GameState;
 use core::pathfinder::Pathfinder;
 use core::core::{
     Core,
+    CommandMoveGroup,
+    IN_COMMAND_AURA,
     Event,
+    UnitType,
     UnitTypeId,
-    CommandCreateUnit,
+    BestWeapon,
+    Simultaneous,
+    OrderMove,
+    OrderAttack,
+    Deployment,
+    Battle,
+    get_best_weapon_index,
+    get_combat_odds,
+    get_indirect_weapon_index,
+    CommandBuyUnit,
     CommandMove,
     CommandAttackUnit,
+    CommandAttackStack,
     CommandEndTurn,
+    CommandLoadUnit,
+    CommandUnloadUnit,
+    CommandAttackPos,
+    CommandLayMines,
+    CommandClearMines,
+    CommandFortify,
+    CommandHealUnit,
+    CommandDeployUnit,
+    CommandReady,
+    CommandUndo,
+    CommandQueueMove,
     EventCreateUnit,
     EventMove,
     EventAttackUnit,
     EventEndTurn,
+    EventChangeResources,
+    EventCaptureObjective,
+    EventChangeScore,
+    EventVictory,
+    EventSuppressUnit,
+    EventPromoteUnit,
+    EventLoadUnit,
+    EventUnloadUnit,
+    EventDestroyUnit,
+    EventDamageVehicle,
+    EventIndirectAttack,
+    EventCreateTileEffect,
+    EventLayMines,
+    EventClearMines,
+    EventRevealMinefield,
+    EventMineExplosion,
+    EventFortify,
+    EventHealUnit,
+    EventRestoreUnit,
+    EventRepairVehicle,
+    EventPlayerReady,
+    EventRevealUnit,
+    EventShowMessage,
+    EventChangeObjective,
+    EventGainExperience,
+    EventDraw,
+    EventOrdersDone,
+    EventUndoMove,
 };
+use core::los::is_los_clear;
 use core::fs::FileSystem;
+use core::campaign::{
+    Campaign,
+    CampaignProgress,
+    get_save_path,
+    get_campaign_path,
+};
 use core::dir::{Dir};
 use visualizer::mgl;
 use visualizer::camera::Camera;
@@ -49,6 +110,21 @@ use visualizer::event_visualizer::{
     EventEndTurnVisualizer,
     EventCreateUnitVisualizer,
     EventAttackUnitVisualizer,
+    EventIndirectAttackVisualizer,
+    EventMineExplosionVisualizer,
+    EventSilentVisualizer,
+    EventCaptureObjectiveVisualizer,
+    EventPromoteUnitVisualizer,
+    EventLoadUnitVisualizer,
+    EventDestroyUnitVisualizer,
+    set_rank_markers,
+    set_alive_nodes_count,
+    reset_unit_nodes,
+    update_stacks_layout,
+    update_tile_effect_nodes,
+    update_minefield_nodes,
+    update_fortification_nodes,
+    TileEffectMeshIds,
 };
 use visualizer::shader::Shader;
 use visualizer::texture::Texture;
@@ -62,7 +138,10 @@ use visualizer::state_visualizer::{
 };
 
 fn get_marker(shader: &Shader, tex_path: &Path) -> Mesh {
-    let n = 0.2;
+    get_marker_s(shader, tex_path, 0.2)
+}
+
+fn get_marker_s(shader: &Shader, tex_path: &Path, n: MFloat) -> Mesh {
     let vertex_data = vec!(
         VertexCoord{v: Vector3{x: -n, y: 0.0, z: 0.1}},
         VertexCoord{v: Vector3{x: 0.0, y: n * 1.4, z: 0.1}},
@@ -107,6 +186,12 @@ fn get_pathfinders(
     m
 }
 
+fn is_drag(from: ScreenPos, to: ScreenPos) -> bool {
+    let min_drag_distance = 10;
+    abs(from.v.x - to.v.x) > min_drag_distance
+        || abs(from.v.y - to.v.y) > min_drag_distance
+}
+
 fn build_walkable_mesh(pathfinder: &Pathfinder, shader: &Shader) -> Mesh {
     let map = pathfinder.get_map();
     let map_size = map.get_size();
@@ -129,6 +214,24 @@ fn build_walkable_mesh(pathfinder: &Pathfinder, shader: &Shader) -> Mesh {
     mesh
 }
 
+// Outlines of deploy zone or selected group tiles
+fn build_tiles_outline_mesh(tiles: &Vec<MapPos>, shader: &Shader) -> Mesh {
+    let mut vertex_data = Vec::new();
+    for tile_pos in tiles.iter() {
+        let pos = geom::map_pos_to_world_pos(*tile_pos);
+        for num in range(0i32, 6) {
+            let vertex = geom::index_to_hex_vertex(num);
+            let next_vertex = geom::index_to_hex_vertex(num + 1);
+            vertex_data.push(VertexCoord{v: geom::lift(pos.v + vertex.v)});
+            vertex_data.push(VertexCoord{v: geom::lift(pos.v + next_vertex.v)});
+        }
+    }
+    let mut mesh = Mesh::new(vertex_data.as_slice());
+    mesh.set_mode(mgl::Lines);
+    mesh.prepare(shader);
+    mesh
+}
+
 fn get_map_mesh(fs: &FileSystem, map_size: Size2<MInt>, shader: &Shader) -> Mesh {
     let mut vertex_data = Vec::new();
     let mut tex_data = Vec::new();
@@ -191,6 +294,11 @@ fn get_marker_mesh_id(
     }
 }
 
+fn get_alive_nodes_count(unit_type: &UnitType, hit_points: MInt) -> MInt {
+    let max_hit_points = unit_type.hit_points;
+    (unit_type.count * hit_points + max_hit_points - 1) / max_hit_points
+}
+
 fn get_unit_mesh_id (
     unit_type_visual_info: &UnitTypeVisualInfoManager,
     unit_type_id: UnitTypeId,
@@ -198,11 +306,22 @@ fn get_unit_mesh_id (
     unit_type_visual_info.get(unit_type_id).mesh_id
 }
 
+struct BuyMenu {
+    pos: MapPos,
+    is_deploy: bool, // deploy roster units instead of buying
+    button_manager: ButtonManager,
+    unit_type_ids: HashMap<ButtonId, UnitTypeId>,
+}
+
 struct MeshIdManager {
     map_mesh_id: MeshId,
     shell_mesh_id: MeshId,
     marker_1_mesh_id: MeshId,
     marker_2_mesh_id: MeshId,
+    rank_mesh_id: MeshId,
+    tile_effect_mesh_ids: TileEffectMeshIds,
+    mine_mesh_id: MeshId,
+    fortification_mesh_id: MeshId,
 }
 
 pub struct GameStateVisualizer {
@@ -210,13 +329,19 @@ pub struct GameStateVisualizer {
     unit_type_visual_info: UnitTypeVisualInfoManager,
     meshes: Vec<Mesh>,
     walkable_mesh: Option<Mesh>, // TODO: move to 'meshes'
+    deploy_zone_meshes: HashMap<PlayerId, Mesh>,
+    orders_mesh: Option<Mesh>, // orders of simultaneous mode
+    group_mesh: Option<Mesh>, // marks selected units except the active one
+    command_radius_mesh: Option<Mesh>, // shown when leader is selected
     map_text_mesh: Mesh,
     camera: Camera,
     commands_rx: Receiver<StateChangeCommand>,
     commands_tx: Sender<StateChangeCommand>,
     picker: picker::TilePicker,
     map_pos_under_cursor: Option<MapPos>,
-    selected_unit_id: Option<UnitId>,
+    selected_unit_id: Option<UnitId>, // active unit of selected group
+    selected_unit_ids: Vec<UnitId>,
+    drag_start_pos: Option<ScreenPos>,
     unit_under_cursor_id: Option<UnitId>,
     scenes: HashMap<PlayerId, Scene>,
     core: Core,
@@ -226,8 +351,17 @@ pub struct GameStateVisualizer {
     pathfinders: HashMap<PlayerId, Pathfinder>,
     button_manager: ButtonManager,
     button_end_turn_id: ButtonId,
+    button_undo_id: ButtonId,
     button_quit_id: ButtonId,
+    buy_menu: Option<BuyMenu>,
+    unit_info: ButtonManager,
+    last_attack_info: Option<String>,
+    message: Option<String>, // last message from scenario
+    odds_tooltip: ButtonManager,
+    odds_tooltip_ids: Option<(UnitId, UnitId)>, // attacker_id, defender_id
     selection_manager: SelectionManager,
+    campaign: Option<(Campaign, CampaignProgress)>, // None if not in campaign
+    campaign_save_path: Path,
 }
 
 fn get_unit_type_visual_info(
@@ -249,14 +383,84 @@ fn get_unit_type_visual_info(
         mesh_id: soldier_mesh_id,
         move_speed: 2.0,
     });
+    // TODO: use separate truck mesh
+    unit_type_visual_info.add_info(UnitTypeVisualInfo {
+        mesh_id: tank_mesh_id,
+        move_speed: 4.5,
+    });
+    // TODO: use separate mortar mesh
+    unit_type_visual_info.add_info(UnitTypeVisualInfo {
+        mesh_id: soldier_mesh_id,
+        move_speed: 1.5,
+    });
+    // TODO: use separate engineer mesh
+    unit_type_visual_info.add_info(UnitTypeVisualInfo {
+        mesh_id: soldier_mesh_id,
+        move_speed: 2.0,
+    });
+    // TODO: use separate medic mesh
+    unit_type_visual_info.add_info(UnitTypeVisualInfo {
+        mesh_id: soldier_mesh_id,
+        move_speed: 2.0,
+    });
+    // TODO: use separate officer mesh
+    unit_type_visual_info.add_info(UnitTypeVisualInfo {
+        mesh_id: soldier_mesh_id,
+        move_speed: 2.0,
+    });
     unit_type_visual_info
 }
 
+// Keys that give orders supported in simultaneous battle
+// or don't give any orders at all
+fn is_simultaneous_order_key(key: glfw::Key) -> bool {
+    match key {
+        glfw::KeyU
+            | glfw::KeyL
+            | glfw::KeyO
+            | glfw::KeyR
+            | glfw::KeyK
+            | glfw::KeyF
+            | glfw::KeyG
+            | glfw::KeyN
+            | glfw::KeyC
+            | glfw::KeyI
+            | glfw::KeyH => false,
+        _ => true,
+    }
+}
+
 impl GameStateVisualizer {
     pub fn new(fs: &FileSystem, context: &Context) -> GameStateVisualizer {
+        GameStateVisualizer::new_with_core(fs, context, Core::new(fs), None)
+    }
+
+    pub fn new_simultaneous(fs: &FileSystem, context: &Context) -> GameStateVisualizer {
+        let mut core = Core::new(fs);
+        core.set_turn_mode(Simultaneous);
+        GameStateVisualizer::new_with_core(fs, context, core, None)
+    }
+
+    // Loads campaign progress and uses it to prepare the battle
+    pub fn new_campaign_mission(
+        fs: &FileSystem,
+        context: &Context,
+    ) -> GameStateVisualizer {
+        let campaign = Campaign::load(&get_campaign_path(fs));
+        let progress = CampaignProgress::load(&get_save_path(fs));
+        let core = progress.create_core(fs, &campaign);
+        GameStateVisualizer::new_with_core(
+            fs, context, core, Some((campaign, progress)))
+    }
+
+    fn new_with_core(
+        fs: &FileSystem,
+        context: &Context,
+        core: Core,
+        campaign: Option<(Campaign, CampaignProgress)>,
+    ) -> GameStateVisualizer {
         set_error_context!("constructing GameStateVisualizer", "-");
         let players_count = 2;
-        let core = Core::new(fs);
         let map_size = core.map_size();
         let game_states = get_game_states(players_count);
         let picker = picker::TilePicker::new(
@@ -281,6 +485,50 @@ impl GameStateVisualizer {
             &mut meshes,
             get_marker(&context.shader, &fs.get(&Path::new("data/flag2.png"))),
         );
+        let rank_mesh_id = add_mesh(
+            &mut meshes,
+            get_marker_s(
+                &context.shader,
+                &fs.get(&Path::new("data/shell.png")),
+                0.08,
+            ),
+        );
+        // TODO: use separate textures for tile effects
+        let tile_effect_mesh_ids = {
+            let shell_tex_path = fs.get(&Path::new("data/shell.png"));
+            TileEffectMeshIds {
+                smoke_mesh_id: add_mesh(
+                    &mut meshes,
+                    get_marker_s(&context.shader, &shell_tex_path, 0.7),
+                ),
+                wreck_mesh_id: add_mesh(
+                    &mut meshes,
+                    get_marker_s(&context.shader, &shell_tex_path, 0.45),
+                ),
+                crater_mesh_id: add_mesh(
+                    &mut meshes,
+                    get_marker_s(&context.shader, &shell_tex_path, 0.3),
+                ),
+            }
+        };
+        // TODO: use separate mine texture
+        let mine_mesh_id = add_mesh(
+            &mut meshes,
+            get_marker_s(
+                &context.shader,
+                &fs.get(&Path::new("data/flag1.png")),
+                0.3,
+            ),
+        );
+        // TODO: use separate fortification texture
+        let fortification_mesh_id = add_mesh(
+            &mut meshes,
+            get_marker_s(
+                &context.shader,
+                &fs.get(&Path::new("data/shell.png")),
+                0.35,
+            ),
+        );
         let mut camera = Camera::new(context.win_size);
         camera.set_max_pos(get_max_camera_pos(&map_size));
         camera.set_pos(get_initial_camera_pos(&map_size));
@@ -291,6 +539,12 @@ impl GameStateVisualizer {
             &context.shader,
             ScreenPos{v: Vector2{x: 10, y: 40}})
         );
+        let button_undo_id = button_manager.add_button(Button::new(
+            "undo move",
+            context.font_stash.borrow_mut().deref_mut(),
+            &context.shader,
+            ScreenPos{v: Vector2{x: 10, y: 70}})
+        );
         let button_quit_id = button_manager.add_button(Button::new(
             "quit",
             context.font_stash.borrow_mut().deref_mut(),
@@ -305,10 +559,23 @@ impl GameStateVisualizer {
             shell_mesh_id: shell_mesh_id,
             marker_1_mesh_id: marker_1_mesh_id,
             marker_2_mesh_id: marker_2_mesh_id,
+            rank_mesh_id: rank_mesh_id,
+            tile_effect_mesh_ids: tile_effect_mesh_ids,
+            mine_mesh_id: mine_mesh_id,
+            fortification_mesh_id: fortification_mesh_id,
         };
+        let mut deploy_zone_meshes = HashMap::new();
+        for (player_id, zone) in core.scenario().deploy_zones.iter() {
+            deploy_zone_meshes.insert(
+                *player_id, build_tiles_outline_mesh(zone, &context.shader));
+        }
         let (commands_tx, commands_rx) = channel();
         let vis = GameStateVisualizer {
             walkable_mesh: None,
+            deploy_zone_meshes: deploy_zone_meshes,
+            orders_mesh: None,
+            group_mesh: None,
+            command_radius_mesh: None,
             unit_type_visual_info: get_unit_type_visual_info(
                 fs, context, &mut meshes),
             mesh_ids: mesh_ids,
@@ -318,6 +585,8 @@ impl GameStateVisualizer {
             picker: picker,
             map_pos_under_cursor: None,
             selected_unit_id: None,
+            selected_unit_ids: Vec::new(),
+            drag_start_pos: None,
             unit_under_cursor_id: None,
             core: core,
             event_visualizer: None,
@@ -327,8 +596,17 @@ impl GameStateVisualizer {
             pathfinders: get_pathfinders(players_count, map_size),
             button_manager: button_manager,
             button_end_turn_id: button_end_turn_id,
+            button_undo_id: button_undo_id,
             button_quit_id: button_quit_id,
+            buy_menu: None,
+            unit_info: ButtonManager::new(),
+            last_attack_info: None,
+            message: None,
+            odds_tooltip: ButtonManager::new(),
+            odds_tooltip_ids: None,
             selection_manager: SelectionManager::new(selection_marker_mesh_id),
+            campaign: campaign,
+            campaign_save_path: get_save_path(fs),
             commands_rx: commands_rx,
             commands_tx: commands_tx,
         };
@@ -391,6 +669,36 @@ impl GameStateVisualizer {
             },
             None => {},
         }
+        match self.orders_mesh {
+            Some(ref orders_mesh) => {
+                context.shader.uniform_color(context.basic_color_id, mgl::RED);
+                orders_mesh.draw(&context.shader);
+            },
+            None => {},
+        }
+        match self.group_mesh {
+            Some(ref group_mesh) => {
+                context.shader.uniform_color(context.basic_color_id, mgl::BLUE);
+                group_mesh.draw(&context.shader);
+            },
+            None => {},
+        }
+        match self.command_radius_mesh {
+            Some(ref command_radius_mesh) => {
+                context.shader.uniform_color(context.basic_color_id, mgl::WHITE);
+                command_radius_mesh.draw(&context.shader);
+            },
+            None => {},
+        }
+        if self.core.phase() == Deployment {
+            match self.deploy_zone_meshes.find(&self.core.player_id()) {
+                Some(mesh) => {
+                    context.shader.uniform_color(context.basic_color_id, mgl::GREEN);
+                    mesh.draw(&context.shader);
+                },
+                None => {},
+            }
+        }
         match self.event_visualizer {
             Some(ref mut event_visualizer) => {
                 let scene = self.scenes.get_mut(&self.core.player_id());
@@ -400,30 +708,145 @@ impl GameStateVisualizer {
         }
     }
 
+    // During deployment this tells core that player is ready
     fn end_turn(&mut self) {
-        self.core.do_command(CommandEndTurn);
+        let command = match self.core.phase() {
+            Deployment => CommandReady,
+            Battle => CommandEndTurn,
+        };
+        self.core.do_command(command);
+        self.buy_menu = None;
+        self.deselect_units();
+    }
+
+    fn deselect_units(&mut self) {
         self.selected_unit_id = None;
+        self.selected_unit_ids.clear();
+        self.group_mesh = None;
+        self.command_radius_mesh = None;
         let scene = self.scenes.get_mut(&self.core.player_id());
         self.selection_manager.deselect(scene);
         self.walkable_mesh = None;
     }
 
-    fn is_tile_occupied(&self, pos: MapPos) -> bool {
-        let state = &self.game_states[self.core.player_id()];
-        state.units_at(pos).len() > 0
+    fn undo_move(&mut self) {
+        if self.core.can_undo() {
+            self.core.do_command(CommandUndo);
+        }
     }
 
-    fn create_unit(&mut self) {
-        match self.map_pos_under_cursor {
-            Some(pos) => {
-                if self.is_tile_occupied(pos) {
-                    return;
+    fn open_buy_menu(&mut self, context: &Context) {
+        let pos = match self.map_pos_under_cursor {
+            Some(pos) => pos,
+            None => return,
+        };
+        if self.core.phase() == Deployment {
+            self.open_deploy_menu(context, pos);
+            return;
+        }
+        let player_id = self.core.player_id();
+        if !self.core.scenario().is_deploy_pos(player_id, pos) {
+            return;
+        }
+        let mut button_manager = ButtonManager::new();
+        let mut unit_type_ids = HashMap::new();
+        let mut y = 110;
+        for type_id in self.core.object_types().get_unit_type_ids().into_iter() {
+            let label = {
+                let unit_type = self.core.object_types().get_unit_type(type_id);
+                format!("{}: {}", unit_type.name, unit_type.cost)
+            };
+            let button_id = button_manager.add_button(Button::new(
+                label.as_slice(),
+                context.font_stash.borrow_mut().deref_mut(),
+                &context.shader,
+                ScreenPos{v: Vector2{x: 10, y: y}})
+            );
+            unit_type_ids.insert(button_id, type_id);
+            y += 30;
+        }
+        let resources = self.game_states[player_id].get_resources(player_id);
+        let _ = button_manager.add_button(Button::new(
+            format!("resources: {}", resources).as_slice(),
+            context.font_stash.borrow_mut().deref_mut(),
+            &context.shader,
+            ScreenPos{v: Vector2{x: 10, y: y}})
+        );
+        self.buy_menu = Some(BuyMenu {
+            pos: pos,
+            is_deploy: false,
+            button_manager: button_manager,
+            unit_type_ids: unit_type_ids,
+        });
+    }
+
+    // Lists not yet deployed unit types of current player's roster
+    fn open_deploy_menu(&mut self, context: &Context, pos: MapPos) {
+        let player_id = self.core.player_id();
+        if !self.core.scenario().is_in_deploy_zone(player_id, pos) {
+            return;
+        }
+        let mut button_manager = ButtonManager::new();
+        let mut unit_type_ids = HashMap::new();
+        let mut y = 110;
+        for type_id in self.core.object_types().get_unit_type_ids().into_iter() {
+            let count = self.core.get_roster(player_id).iter()
+                .filter(|unit| unit.type_id.id == type_id.id).count();
+            if count == 0 {
+                continue;
+            }
+            let label = {
+                let unit_type = self.core.object_types().get_unit_type(type_id);
+                format!("{}: x{}", unit_type.name, count)
+            };
+            let button_id = button_manager.add_button(Button::new(
+                label.as_slice(),
+                context.font_stash.borrow_mut().deref_mut(),
+                &context.shader,
+                ScreenPos{v: Vector2{x: 10, y: y}})
+            );
+            unit_type_ids.insert(button_id, type_id);
+            y += 30;
+        }
+        self.buy_menu = Some(BuyMenu {
+            pos: pos,
+            is_deploy: true,
+            button_manager: button_manager,
+            unit_type_ids: unit_type_ids,
+        });
+    }
+
+    // Returns true if click was consumed by buy menu
+    fn handle_buy_menu_click(&mut self, context: &Context) -> bool {
+        let (is_clicked, command) = match self.buy_menu {
+            Some(ref buy_menu) => {
+                match buy_menu.button_manager.get_clicked_button_id(context) {
+                    Some(button_id) => {
+                        let command = buy_menu.unit_type_ids.find(&button_id)
+                            .map(|type_id| if buy_menu.is_deploy {
+                                CommandDeployUnit(*type_id, buy_menu.pos)
+                            } else {
+                                CommandBuyUnit(*type_id, buy_menu.pos)
+                            });
+                        (true, command)
+                    },
+                    None => (false, None),
                 }
-                let cmd = CommandCreateUnit(pos);
-                self.core.do_command(cmd);
+            },
+            None => return false,
+        };
+        if !is_clicked {
+            self.buy_menu = None;
+            return false;
+        }
+        match command {
+            Some(command) => {
+                self.buy_menu = None;
+                self.core.do_command(command);
             },
             None => {},
         }
+        true
     }
 
     fn attack_unit(&mut self) {
@@ -431,45 +854,451 @@ impl GameStateVisualizer {
             (Some(defender_id), Some(attacker_id)) => {
                 let state = &self.game_states[self.core.player_id()];
                 let attacker = &state.units[attacker_id];
-                if attacker.attacked {
-                    return;
-                }
                 let defender = &state.units[defender_id];
-                let max_distance = {
-                    let attacker_type = self.core.object_types()
-                        .get_unit_type(attacker.type_id);
-                    let weapon_type = self.core.get_weapon_type(
-                        attacker_type.weapon_type_id);
-                    weapon_type.max_distance
-                };
-                if distance(attacker.pos, defender.pos) > max_distance {
+                let weapon_index = get_best_weapon_index(
+                    self.core.rules(), self.core.object_types(),
+                    attacker, defender);
+                if weapon_index.is_none() {
                     return;
                 }
-                let cmd = CommandAttackUnit(attacker_id, defender_id);
+                let cmd = CommandAttackUnit(
+                    attacker_id, defender_id, BestWeapon);
                 self.core.do_command(cmd);
             },
             _ => {},
        }
     }
 
+    fn attack_stack(&mut self) {
+        let attacker_id = match self.selected_unit_id {
+            Some(unit_id) => unit_id,
+            None => return,
+        };
+        let pos = {
+            let state = &self.game_states[self.core.player_id()];
+            match (self.map_pos_under_cursor, self.unit_under_cursor_id) {
+                (Some(pos), _) => pos,
+                (None, Some(unit_id)) => state.units[unit_id].pos,
+                (None, None) => return,
+            }
+        };
+        self.core.do_command(CommandAttackStack(attacker_id, pos, BestWeapon));
+    }
+
+    fn fire_at_pos(&mut self, is_smoke: bool) {
+        let attacker_id = match self.selected_unit_id {
+            Some(unit_id) => unit_id,
+            None => return,
+        };
+        let command = {
+            let state = &self.game_states[self.core.player_id()];
+            let pos = match (self.map_pos_under_cursor, self.unit_under_cursor_id) {
+                (Some(pos), _) => pos,
+                (None, Some(unit_id)) => state.units[unit_id].pos,
+                (None, None) => return,
+            };
+            let attacker = &state.units[attacker_id];
+            match get_indirect_weapon_index(
+                self.core.object_types(), attacker, is_smoke)
+            {
+                Some(weapon_index) => {
+                    CommandAttackPos(attacker_id, pos, weapon_index)
+                },
+                None => return,
+            }
+        };
+        self.core.do_command(command);
+    }
+
+    // Selected medic or supply unit heals unit under cursor
+    fn heal_unit(&mut self) {
+        match (self.selected_unit_id, self.unit_under_cursor_id) {
+            (Some(healer_id), Some(unit_id)) => {
+                self.core.do_command(CommandHealUnit(healer_id, unit_id));
+            },
+            _ => {},
+        }
+    }
+
+    fn fortify_unit(&mut self) {
+        match self.selected_unit_id {
+            Some(unit_id) => self.core.do_command(CommandFortify(unit_id)),
+            None => {},
+        }
+    }
+
+    fn handle_mines(&mut self, is_clearing: bool) {
+        let (unit_id, pos) = match (self.selected_unit_id, self.map_pos_under_cursor) {
+            (Some(unit_id), Some(pos)) => (unit_id, pos),
+            _ => return,
+        };
+        let command = if is_clearing {
+            CommandClearMines(unit_id, pos)
+        } else {
+            CommandLayMines(unit_id, pos)
+        };
+        self.core.do_command(command);
+    }
+
+    // Destination can be farther than unit can go in one turn
+    fn queue_move(&mut self) {
+        let (unit_id, pos) = match (self.selected_unit_id, self.map_pos_under_cursor) {
+            (Some(unit_id), Some(pos)) => (unit_id, pos),
+            _ => return,
+        };
+        let path = {
+            let state = &self.game_states[self.core.player_id()];
+            let unit = &state.units[unit_id];
+            let can_stack = state.can_stack(
+                self.core.object_types(),
+                self.core.scenario().stack_limit,
+                pos,
+                unit.player_id,
+                unit.type_id,
+            );
+            let pf = self.pathfinders.get_mut(&self.core.player_id());
+            pf.fill_map_unlimited(state, unit);
+            let path = if can_stack && pf.is_reachable(pos) {
+                pf.get_path(pos)
+            } else {
+                vec![]
+            };
+            pf.fill_map(state, unit);
+            path
+        };
+        if path.len() < 2 {
+            return;
+        }
+        self.core.do_command(CommandQueueMove(unit_id, path));
+    }
+
+    fn move_unit_to_stack(&mut self) {
+        let pos = match self.unit_under_cursor_id {
+            Some(unit_id) => {
+                let state = &self.game_states[self.core.player_id()];
+                state.units[unit_id].pos
+            },
+            None => return,
+        };
+        self.move_unit(pos);
+    }
+
+    fn load_unit(&mut self) {
+        match (self.unit_under_cursor_id, self.selected_unit_id) {
+            (Some(transporter_id), Some(passenger_id)) => {
+                if transporter_id == passenger_id {
+                    return;
+                }
+                self.core.do_command(CommandLoadUnit(passenger_id, transporter_id));
+                self.deselect_units();
+            },
+            _ => {},
+        }
+    }
+
+    fn unload_unit(&mut self) {
+        let pos = match self.map_pos_under_cursor {
+            Some(pos) => pos,
+            None => return,
+        };
+        let transporter_id = match self.selected_unit_id {
+            Some(transporter_id) => transporter_id,
+            None => return,
+        };
+        let passenger_id = {
+            let state = &self.game_states[self.core.player_id()];
+            let transporter = &state.units[transporter_id];
+            let mut passenger_id = None;
+            for id in transporter.passenger_ids.iter() {
+                if state.units[*id].move_points > 0 {
+                    passenger_id = Some(*id);
+                    break;
+                }
+            }
+            passenger_id
+        };
+        match passenger_id {
+            Some(passenger_id) => {
+                self.core.do_command(CommandUnloadUnit(passenger_id, pos));
+            },
+            None => {},
+        }
+    }
+
+    fn activate_unit(&mut self, context: &Context, unit_id: UnitId) {
+        self.selected_unit_id = Some(unit_id);
+        let state = &self.game_states[self.core.player_id()];
+        let pf = self.pathfinders.get_mut(&self.core.player_id());
+        pf.fill_map(state, &state.units[unit_id]);
+        self.walkable_mesh = Some(build_walkable_mesh(pf, &context.shader));
+        let scene = self.scenes.get_mut(&self.core.player_id());
+        self.selection_manager.create_selection_marker(
+            state, scene, unit_id);
+        // TODO: highlight potential targets
+        self.update_command_radius_mesh(context);
+    }
+
+    fn update_command_radius_mesh(&mut self, context: &Context) {
+        let tiles = {
+            let state = &self.game_states[self.core.player_id()];
+            let unit = match self.selected_unit_id {
+                Some(unit_id) => state.units.find(&unit_id),
+                None => None,
+            };
+            match unit {
+                Some(unit) => {
+                    let unit_type = self.core.object_types()
+                        .get_unit_type(unit.type_id);
+                    if unit_type.is_leader() {
+                        MapPosIter::new(self.core.map_size())
+                            .filter(|pos| {
+                                distance(unit.pos, *pos) <= unit_type.command_radius
+                            })
+                            .collect()
+                    } else {
+                        Vec::new()
+                    }
+                },
+                None => Vec::new(),
+            }
+        };
+        self.command_radius_mesh = if tiles.len() == 0 {
+            None
+        } else {
+            Some(build_tiles_outline_mesh(&tiles, &context.shader))
+        };
+    }
+
+    fn update_group_mesh(&mut self, context: &Context) {
+        let positions = {
+            let state = &self.game_states[self.core.player_id()];
+            self.selected_unit_ids.iter()
+                .filter(|id| Some(**id) != self.selected_unit_id)
+                .map(|id| state.units[*id].pos)
+                .collect::<Vec<MapPos>>()
+        };
+        self.group_mesh = if positions.len() == 0 {
+            None
+        } else {
+            Some(build_tiles_outline_mesh(&positions, &context.shader))
+        };
+    }
+
+    fn is_shift_pressed(&self, context: &Context) -> bool {
+        context.win.get_key(glfw::KeyLeftShift) == glfw::Press
+            || context.win.get_key(glfw::KeyRightShift) == glfw::Press
+    }
+
+    // Shift adds unit to selected group
     fn select_unit(&mut self, context: &Context) {
         match self.unit_under_cursor_id {
             Some(unit_id) => {
-                self.selected_unit_id = Some(unit_id);
-                let state = &self.game_states[self.core.player_id()];
-                let pf = self.pathfinders.get_mut(&self.core.player_id());
-                pf.fill_map(state, &state.units[unit_id]);
-                self.walkable_mesh = Some(build_walkable_mesh(pf, &context.shader));
-                let scene = self.scenes.get_mut(&self.core.player_id());
-                self.selection_manager.create_selection_marker(
-                    state, scene, unit_id);
-                // TODO: highlight potential targets
+                if !self.is_shift_pressed(context) {
+                    self.selected_unit_ids.clear();
+                }
+                if !self.selected_unit_ids.contains(&unit_id) {
+                    self.selected_unit_ids.push(unit_id);
+                }
+                self.activate_unit(context, unit_id);
+                self.update_group_mesh(context);
             },
             None => {},
         }
+        self.update_unit_info(context);
+    }
+
+    fn select_units_in_box(&mut self, context: &Context, a: ScreenPos, b: ScreenPos) {
+        let min_x = min(a.v.x, b.v.x);
+        let max_x = max(a.v.x, b.v.x);
+        let min_y = min(a.v.y, b.v.y);
+        let max_y = max(a.v.y, b.v.y);
+        let mut unit_ids = if self.is_shift_pressed(context) {
+            self.selected_unit_ids.clone()
+        } else {
+            Vec::new()
+        };
+        {
+            let player_id = self.core.player_id();
+            let state = &self.game_states[player_id];
+            let camera_mat = self.camera.mat();
+            for unit in state.units.values() {
+                if unit.player_id != player_id || unit.is_loaded()
+                    || unit_ids.contains(&unit.id)
+                {
+                    continue;
+                }
+                let world_pos = geom::map_pos_to_world_pos(unit.pos);
+                let screen_pos = mgl::world_to_screen_pos(
+                    camera_mat, context.win_size, world_pos.v);
+                match screen_pos {
+                    Some(ScreenPos{v: Vector2{x, y}}) => {
+                        if x >= min_x && x <= max_x && y >= min_y && y <= max_y {
+                            unit_ids.push(unit.id);
+                        }
+                    },
+                    None => {},
+                }
+            }
+        }
+        unit_ids.sort();
+        if unit_ids.len() == 0 {
+            self.deselect_units();
+        } else {
+            let active_unit_id = match self.selected_unit_id {
+                Some(unit_id) if unit_ids.contains(&unit_id) => unit_id,
+                _ => unit_ids[0],
+            };
+            self.selected_unit_ids = unit_ids;
+            self.activate_unit(context, active_unit_id);
+            self.update_group_mesh(context);
+        }
+        self.update_unit_info(context);
+    }
+
+    fn get_unit_info_lines(&self) -> Vec<String> {
+        let mut lines = vec![format!("round {}/{}",
+            self.core.round(), self.core.scenario().round_limit)];
+        match self.message {
+            Some(ref message) => lines.push(message.clone()),
+            None => {},
+        }
+        match self.last_attack_info {
+            Some(ref info) => lines.push(info.clone()),
+            None => {},
+        }
+        if self.selected_unit_ids.len() > 1 {
+            lines.push(format!("group: {} units", self.selected_unit_ids.len()));
+        }
+        let unit_id = match self.selected_unit_id {
+            Some(unit_id) => unit_id,
+            None => return lines,
+        };
+        let state = &self.game_states[self.core.player_id()];
+        let unit = match state.units.find(&unit_id) {
+            Some(unit) => unit,
+            None => return lines,
+        };
+        let unit_type = self.core.object_types().get_unit_type(unit.type_id);
+        lines.push(format!("{}: hp {}/{}",
+            unit_type.name, unit.hit_points, unit_type.hit_points));
+        if unit.immobilized {
+            lines.push("immobilized".to_string());
+        }
+        if unit_type.is_leader() {
+            lines.push(format!("command radius: {}", unit_type.command_radius));
+        }
+        lines.push(if unit.aura == IN_COMMAND_AURA {
+            "in command".to_string()
+        } else {
+            "out of command".to_string()
+        });
+        if unit.fortification > 0 {
+            lines.push(format!("fortification: {}", unit.fortification));
+        }
+        if unit_type.is_engineer() {
+            lines.push(format!("mines: {}", unit.mines));
+        }
+        for weapon in unit.weapons.iter() {
+            if weapon.destroyed {
+                let weapon_type = self.core.get_weapon_type(weapon.type_id);
+                lines.push(format!("{} destroyed", weapon_type.name));
+            }
+        }
+        lines
+    }
+
+    fn update_unit_info(&mut self, context: &Context) {
+        let mut unit_info = ButtonManager::new();
+        let mut y = context.win_size.h - 70;
+        for line in self.get_unit_info_lines().iter() {
+            let _ = unit_info.add_button(Button::new(
+                line.as_slice(),
+                context.font_stash.borrow_mut().deref_mut(),
+                &context.shader,
+                ScreenPos{v: Vector2{x: 10, y: y}})
+            );
+            y -= 30;
+        }
+        self.unit_info = unit_info;
+    }
+
+    fn get_odds_tooltip_ids(&self) -> Option<(UnitId, UnitId)> {
+        if self.event_visualizer.is_some() {
+            return None;
+        }
+        match (self.selected_unit_id, self.unit_under_cursor_id) {
+            (Some(attacker_id), Some(defender_id)) => {
+                let state = &self.game_states[self.core.player_id()];
+                if !state.units.contains_key(&attacker_id) {
+                    return None;
+                }
+                let defender = &state.units[defender_id];
+                if defender.player_id == self.core.player_id() {
+                    None
+                } else {
+                    Some((attacker_id, defender_id))
+                }
+            },
+            _ => None,
+        }
+    }
+
+    // Shows probabilities of the best weapon when enemy unit is under cursor
+    fn update_odds_tooltip(&mut self, context: &Context) {
+        let ids = self.get_odds_tooltip_ids();
+        if ids == self.odds_tooltip_ids {
+            return;
+        }
+        self.odds_tooltip_ids = ids;
+        self.odds_tooltip = ButtonManager::new();
+        let (attacker_id, defender_id) = match ids {
+            Some(ids) => ids,
+            None => return,
+        };
+        let label = {
+            let state = &self.game_states[self.core.player_id()];
+            let attacker = &state.units[attacker_id];
+            let defender = &state.units[defender_id];
+            let rules = self.core.rules();
+            let object_types = self.core.object_types();
+            let weapon_index = if is_los_clear(state, attacker.pos, defender.pos) {
+                get_best_weapon_index(rules, object_types, attacker, defender)
+            } else {
+                None
+            };
+            match weapon_index {
+                Some(weapon_index) => {
+                    let odds = get_combat_odds(
+                        rules, object_types, attacker, defender, weapon_index);
+                    let weapon_type_id
+                        = attacker.weapons[weapon_index as uint].type_id;
+                    format!("{}: hit {}% pierce {}% kill {}%",
+                        self.core.get_weapon_type(weapon_type_id).name,
+                        (odds.hit * 100.0) as MInt,
+                        (odds.pierce * 100.0) as MInt,
+                        (odds.kill * 100.0) as MInt,
+                    )
+                },
+                None => "can't attack".to_string(),
+            }
+        };
+        let pos = ScreenPos{v: Vector2{
+            x: context.mouse_pos.v.x + 20,
+            y: context.win_size.h - context.mouse_pos.v.y,
+        }};
+        let _ = self.odds_tooltip.add_button(Button::new(
+            label.as_slice(),
+            context.font_stash.borrow_mut().deref_mut(),
+            &context.shader,
+            pos,
+        ));
+    }
+
+    fn is_simultaneous_battle(&self) -> bool {
+        self.core.phase() == Battle && self.core.turn_mode() == Simultaneous
     }
 
-    fn handle_key_event(&mut self, _: &Context, key: glfw::Key) {
+    fn handle_key_event(&mut self, context: &Context, key: glfw::Key) {
         match key {
             glfw::KeyEscape | glfw::KeyQ => self.commands_tx.send(EndGame),
             glfw::KeyUp | glfw::KeyW => self.camera.move_camera(270.0, 0.1),
@@ -483,9 +1312,27 @@ impl GameStateVisualizer {
         if self.event_visualizer.is_some() {
             return;
         }
+        if self.is_simultaneous_battle() && !is_simultaneous_order_key(key) {
+            self.message = Some(
+                "only move and attack orders in simultaneous mode".to_string());
+            self.update_unit_info(context);
+            return;
+        }
         match key {
             glfw::KeyT => self.end_turn(),
-            glfw::KeyU => self.create_unit(),
+            glfw::KeyZ => self.undo_move(),
+            glfw::KeyU => self.open_buy_menu(context),
+            glfw::KeyL => self.load_unit(),
+            glfw::KeyO => self.unload_unit(),
+            glfw::KeyM => self.move_unit_to_stack(),
+            glfw::KeyR => self.queue_move(),
+            glfw::KeyK => self.attack_stack(),
+            glfw::KeyF => self.fire_at_pos(false),
+            glfw::KeyG => self.fire_at_pos(true),
+            glfw::KeyN => self.handle_mines(false),
+            glfw::KeyC => self.handle_mines(true),
+            glfw::KeyI => self.fortify_unit(),
+            glfw::KeyH => self.heal_unit(),
             _ => {},
         }
     }
@@ -501,13 +1348,70 @@ impl GameStateVisualizer {
         }
     }
 
-    fn move_unit(&mut self) {
-        let pos = self.map_pos_under_cursor.unwrap();
+    // Units keep their places relative to the active unit where possible
+    fn get_group_moves(&mut self, pos: MapPos) -> Vec<(UnitId, Vec<MapPos>)> {
+        let player_id = self.core.player_id();
+        let active_unit_id = self.selected_unit_id.unwrap();
+        let state = &self.game_states[player_id];
+        let pf = self.pathfinders.get_mut(&player_id);
+        let active_unit_pos = state.units[active_unit_id].pos;
+        let mut unit_ids = self.selected_unit_ids.clone();
+        // Front units go first and free tiles for units behind them
+        unit_ids.sort_by(|a, b| {
+            let distance_a = distance(state.units[*a].pos, pos);
+            let distance_b = distance(state.units[*b].pos, pos);
+            distance_a.cmp(&distance_b)
+        });
+        let mut taken_positions = Vec::new();
+        let mut moves = Vec::new();
+        for unit_id in unit_ids.iter() {
+            let unit = &state.units[*unit_id];
+            let formation_pos = shift_pos(unit.pos, active_unit_pos, pos);
+            pf.fill_map(state, unit);
+            let mut best_pos = unit.pos;
+            for tile_pos in MapPosIter::new(self.core.map_size()) {
+                if !pf.is_reachable(tile_pos)
+                    || taken_positions.contains(&tile_pos)
+                    || distance(tile_pos, formation_pos)
+                        >= distance(best_pos, formation_pos)
+                {
+                    continue;
+                }
+                let can_stack = state.can_stack(
+                    self.core.object_types(),
+                    self.core.scenario().stack_limit,
+                    tile_pos,
+                    unit.player_id,
+                    unit.type_id,
+                );
+                if can_stack {
+                    best_pos = tile_pos;
+                }
+            }
+            taken_positions.push(best_pos);
+            let path = pf.get_path(best_pos);
+            if path.len() >= 2 {
+                moves.push((*unit_id, path));
+            }
+        }
+        pf.fill_map(state, &state.units[active_unit_id]);
+        moves
+    }
+
+    fn move_group(&mut self, pos: MapPos) {
+        let moves = self.get_group_moves(pos);
+        if moves.len() != 0 {
+            self.core.do_command(CommandMoveGroup(moves));
+        }
+    }
+
+    fn move_unit(&mut self, pos: MapPos) {
         let unit_id = match self.selected_unit_id {
             Some(unit_id) => unit_id,
             None => return,
         };
-        if self.is_tile_occupied(pos) {
+        if self.selected_unit_ids.len() > 1 {
+            self.move_group(pos);
             return;
         }
         let state = &self.game_states[self.core.player_id()];
@@ -515,6 +1419,16 @@ impl GameStateVisualizer {
         if unit.move_points == 0 {
             return;
         }
+        let can_stack = state.can_stack(
+            self.core.object_types(),
+            self.core.scenario().stack_limit,
+            pos,
+            unit.player_id,
+            unit.type_id,
+        );
+        if !can_stack {
+            return;
+        }
         let pf = self.pathfinders.get_mut(&self.core.player_id());
         let path = pf.get_path(pos);
         if path.len() < 2 {
@@ -531,6 +1445,8 @@ impl GameStateVisualizer {
             Some(button_id) => {
                 if button_id == self.button_end_turn_id {
                     self.end_turn();
+                } else if button_id == self.button_undo_id {
+                    self.undo_move();
                 } else if button_id == self.button_quit_id {
                     self.commands_tx.send(EndGame);
                 } else {
@@ -540,8 +1456,12 @@ impl GameStateVisualizer {
             },
             None => {},
         }
-        if self.map_pos_under_cursor.is_some() {
-            self.move_unit();
+        if self.handle_buy_menu_click(context) {
+            return;
+        }
+        match self.map_pos_under_cursor {
+            Some(pos) => self.move_unit(pos),
+            None => {},
         }
         match self.unit_under_cursor_id {
             Some(unit_under_cursor_id) => {
@@ -593,6 +1513,14 @@ impl GameStateVisualizer {
             EventEndTurn(_, _) => {
                 EventEndTurnVisualizer::new()
             },
+            EventUndoMove(ref info) => {
+                let type_id = state.units[info.unit_id].type_id;
+                let unit_type_visual_info = self.unit_type_visual_info.get(type_id);
+                let mut path = info.path.clone();
+                path.reverse();
+                EventMoveVisualizer::new(
+                    scene, state, info.unit_id, unit_type_visual_info, path)
+            },
             EventCreateUnit(id, ref pos, type_id, player_id) => {
                 EventCreateUnitVisualizer::new(
                     &self.core,
@@ -605,43 +1533,339 @@ impl GameStateVisualizer {
                     get_marker_mesh_id(&self.mesh_ids, player_id),
                 )
             },
-            EventAttackUnit(attacker_id, defender_id, killed) => {
+            EventRevealUnit(id, ref pos, type_id, player_id, _, rank) => {
+                let vis = EventCreateUnitVisualizer::new(
+                    &self.core,
+                    scene,
+                    state,
+                    id,
+                    type_id,
+                    *pos,
+                    get_unit_mesh_id(&self.unit_type_visual_info, type_id),
+                    get_marker_mesh_id(&self.mesh_ids, player_id),
+                );
+                set_rank_markers(scene, id, rank, self.mesh_ids.rank_mesh_id);
+                vis
+            },
+            EventAttackUnit(attacker_id, defender_id, _, damage) => {
+                let defender = &state.units[defender_id];
+                let defender_type = self.core.object_types()
+                    .get_unit_type(defender.type_id);
+                let hit_points = defender.hit_points - damage;
                 EventAttackUnitVisualizer::new(
                     scene,
                     state,
                     attacker_id,
                     defender_id,
-                    killed,
+                    hit_points <= 0,
+                    get_alive_nodes_count(defender_type, hit_points),
+                    self.mesh_ids.shell_mesh_id,
+                )
+            },
+            EventChangeResources(_, _) => {
+                EventSilentVisualizer::new()
+            },
+            EventCaptureObjective(objective_id, player_id) => {
+                let pos = self.core.scenario().get_objective(objective_id).pos;
+                EventCaptureObjectiveVisualizer::new(
+                    scene,
+                    state,
+                    objective_id,
+                    pos,
+                    get_marker_mesh_id(&self.mesh_ids, player_id),
+                )
+            },
+            EventPromoteUnit(unit_id, rank) => {
+                EventPromoteUnitVisualizer::new(
+                    scene,
+                    state,
+                    unit_id,
+                    rank,
+                    self.mesh_ids.rank_mesh_id,
+                )
+            },
+            EventLoadUnit(passenger_id, transporter_id) => {
+                EventLoadUnitVisualizer::new(
+                    scene, state, passenger_id, transporter_id)
+            },
+            EventUnloadUnit(unit_id, pos) => {
+                let unit = &state.units[unit_id];
+                let vis = EventCreateUnitVisualizer::new(
+                    &self.core,
+                    scene,
+                    state,
+                    unit_id,
+                    unit.type_id,
+                    pos,
+                    get_unit_mesh_id(&self.unit_type_visual_info, unit.type_id),
+                    get_marker_mesh_id(&self.mesh_ids, unit.player_id),
+                );
+                set_rank_markers(
+                    scene, unit_id, unit.rank, self.mesh_ids.rank_mesh_id);
+                let unit_type = self.core.object_types().get_unit_type(unit.type_id);
+                set_alive_nodes_count(scene, unit_id,
+                    get_alive_nodes_count(unit_type, unit.hit_points));
+                vis
+            },
+            EventDestroyUnit(unit_id) => {
+                EventDestroyUnitVisualizer::new(scene, state, unit_id)
+            },
+            EventIndirectAttack(attacker_id, _, impact_pos, ref hits) => {
+                let mut alive_nodes_counts = Vec::new();
+                for hit in hits.iter() {
+                    let unit = &state.units[hit.unit_id];
+                    let unit_type = self.core.object_types()
+                        .get_unit_type(unit.type_id);
+                    let hit_points = unit.hit_points - hit.damage;
+                    let count = if hit_points <= 0 {
+                        0
+                    } else {
+                        get_alive_nodes_count(unit_type, hit_points)
+                    };
+                    alive_nodes_counts.push((hit.unit_id, count));
+                }
+                EventIndirectAttackVisualizer::new(
+                    scene,
+                    state,
+                    attacker_id,
+                    impact_pos,
+                    alive_nodes_counts,
+                    self.mesh_ids.shell_mesh_id,
+                )
+            },
+            EventHealUnit(_, unit_id, hit_points)
+                | EventRestoreUnit(unit_id, hit_points) =>
+            {
+                let unit = &state.units[unit_id];
+                let unit_type = self.core.object_types().get_unit_type(unit.type_id);
+                let hit_points = min(
+                    unit.hit_points + hit_points, unit_type.hit_points);
+                reset_unit_nodes(
+                    &self.core,
+                    scene,
+                    unit_id,
+                    unit.type_id,
+                    get_unit_mesh_id(&self.unit_type_visual_info, unit.type_id),
+                    get_alive_nodes_count(unit_type, hit_points),
+                );
+                EventSilentVisualizer::new()
+            },
+            EventMineExplosion(unit_id, damage) => {
+                let unit = &state.units[unit_id];
+                let unit_type = self.core.object_types().get_unit_type(unit.type_id);
+                let hit_points = unit.hit_points - damage;
+                let alive_nodes_count = if hit_points <= 0 {
+                    0
+                } else {
+                    get_alive_nodes_count(unit_type, hit_points)
+                };
+                EventMineExplosionVisualizer::new(
+                    scene,
+                    state,
+                    unit_id,
+                    alive_nodes_count,
                     self.mesh_ids.shell_mesh_id,
                 )
             },
+            EventChangeScore(_, _)
+                | EventDamageVehicle(_, _)
+                | EventRepairVehicle(_, _)
+                | EventCreateTileEffect(_, _)
+                | EventLayMines(_, _)
+                | EventClearMines(_, _)
+                | EventRevealMinefield(_, _)
+                | EventFortify(_)
+                | EventVictory(_)
+                | EventPlayerReady(_)
+                | EventShowMessage(_)
+                | EventChangeObjective(_, _, _)
+                | EventGainExperience(_, _)
+                | EventDraw
+                | EventOrdersDone(_)
+                | EventSuppressUnit(_, _, _) =>
+            {
+                EventSilentVisualizer::new()
+            },
         }
     }
 
     fn start_event_visualization(&mut self, event: Event) {
+        match event {
+            EventAttackUnit(_, defender_id, _, damage) => {
+                let state = &self.game_states[self.core.player_id()];
+                let defender = &state.units[defender_id];
+                self.last_attack_info = Some(if damage == 0 {
+                    "miss".to_string()
+                } else if damage >= defender.hit_points {
+                    "target destroyed".to_string()
+                } else {
+                    format!("damage: {}", damage)
+                });
+            },
+            EventIndirectAttack(_, _, _, ref hits) => {
+                let damaged_count = hits.iter().filter(|h| h.damage > 0).count();
+                self.last_attack_info = Some(
+                    format!("blast: {} units damaged", damaged_count));
+            },
+            EventShowMessage(ref text) => {
+                self.message = Some(text.clone());
+            },
+            _ => {},
+        }
         let vis = self.make_event_visualizer(&event);
         self.event = Some(event);
         self.event_visualizer = Some(vis);
     }
 
+    // Paths of move orders and lines from attackers to their targets
+    fn update_orders_mesh(&mut self, context: &Context) {
+        let vertex_data = {
+            let player_id = self.core.player_id();
+            let state = &self.game_states[player_id];
+            let mut vertex_data = Vec::new();
+            let add_line = |vertex_data: &mut Vec<VertexCoord>, from: MapPos, to: MapPos| {
+                let from = geom::map_pos_to_world_pos(from);
+                let to = geom::map_pos_to_world_pos(to);
+                vertex_data.push(VertexCoord{v: geom::lift(from.v)});
+                vertex_data.push(VertexCoord{v: geom::lift(to.v)});
+            };
+            for unit in state.units.values() {
+                if unit.player_id != player_id {
+                    continue;
+                }
+                match self.core.get_queued_move(unit.id) {
+                    Some(queued_move) => {
+                        let path = &queued_move.path;
+                        for i in range(1, path.len()) {
+                            add_line(&mut vertex_data, path[i - 1], path[i]);
+                        }
+                    },
+                    None => {},
+                }
+            }
+            for order in self.core.get_orders(player_id).iter() {
+                match *order {
+                    OrderMove(_, ref path) => {
+                        for i in range(1, path.len()) {
+                            add_line(&mut vertex_data, path[i - 1], path[i]);
+                        }
+                    },
+                    OrderAttack(attacker_id, defender_id, _) => {
+                        let attacker = state.units.find(&attacker_id);
+                        let defender = state.units.find(&defender_id);
+                        match (attacker, defender) {
+                            (Some(attacker), Some(defender)) => {
+                                add_line(&mut vertex_data, attacker.pos, defender.pos);
+                            },
+                            _ => {},
+                        }
+                    },
+                }
+            }
+            vertex_data
+        };
+        self.orders_mesh = if vertex_data.len() == 0 {
+            None
+        } else {
+            let mut mesh = Mesh::new(vertex_data.as_slice());
+            mesh.set_mode(mgl::Lines);
+            mesh.prepare(&context.shader);
+            Some(mesh)
+        };
+    }
+
+    fn get_game_over_lines(&self, winner_id: Option<PlayerId>) -> Vec<String> {
+        let mut lines = vec![match winner_id {
+            Some(winner_id) => format!("player {} wins", winner_id.id),
+            None => "draw".to_string(),
+        }];
+        for i in range(0, self.game_states.len() as MInt) {
+            let score = self.core.get_score_breakdown(PlayerId{id: i});
+            lines.push(format!(
+                "player {}: objectives {}, kills {}, losses {}, total {}",
+                i,
+                score.objectives,
+                score.kills,
+                score.losses,
+                score.total(),
+            ));
+        }
+        lines
+    }
+
+    fn show_game_over(&mut self, context: &Context, winner_id: Option<PlayerId>) {
+        let mut y = context.win_size.h - 40;
+        for line in self.get_game_over_lines(winner_id).iter() {
+            let _ = self.button_manager.add_button(Button::new(
+                line.as_slice(),
+                context.font_stash.borrow_mut().deref_mut(),
+                &context.shader,
+                ScreenPos{v: Vector2{x: context.win_size.w / 3, y: y}})
+            );
+            y -= 30;
+        }
+    }
+
+    // Campaign player is always player 0, draw counts as defeat
+    fn finish_campaign_mission(&mut self, winner_id: Option<PlayerId>) {
+        let (campaign, mut progress) = match self.campaign.take() {
+            Some(campaign) => campaign,
+            None => return,
+        };
+        let player_id = PlayerId{id: 0};
+        progress.finish_mission(
+            &campaign,
+            winner_id == Some(player_id),
+            self.core.export_units(player_id),
+            self.core.export_resources(player_id),
+        );
+        progress.save(&self.campaign_save_path);
+    }
+
     fn end_event_visualization(&mut self, context: &Context) {
-        let scene = self.scenes.get_mut(&self.core.player_id());
-        let state = self.game_states.get_mut(&self.core.player_id());
-        self.event_visualizer.as_mut().unwrap().end(scene, state);
-        state.apply_event(self.core.object_types(), self.event.as_ref().unwrap());
-        self.event_visualizer = None;
-        self.event = None;
-        match self.selected_unit_id {
-            Some(selected_unit_id) => {
-                let pf = self.pathfinders.get_mut(&self.core.player_id());
-                pf.fill_map(state, &state.units[selected_unit_id]);
-                self.walkable_mesh = Some(
-                    build_walkable_mesh(pf, &context.shader));
-                self.selection_manager.move_selection_marker(state, scene);
+        let game_over = match self.event {
+            Some(EventVictory(player_id)) => Some(Some(player_id)),
+            Some(EventDraw) => Some(None),
+            _ => None,
+        };
+        match game_over {
+            Some(winner_id) => {
+                self.show_game_over(context, winner_id);
+                self.finish_campaign_mission(winner_id);
             },
             None => {},
         }
-        self.picker.update_units(state);
+        {
+            let scene = self.scenes.get_mut(&self.core.player_id());
+            let state = self.game_states.get_mut(&self.core.player_id());
+            self.event_visualizer.as_mut().unwrap().end(scene, state);
+            state.apply_event(
+                self.core.object_types(), self.event.as_ref().unwrap());
+            self.selected_unit_ids.retain(|id| state.units.find(id).is_some());
+            self.event_visualizer = None;
+            self.event = None;
+            match self.selected_unit_id {
+                Some(selected_unit_id) => {
+                    let pf = self.pathfinders.get_mut(&self.core.player_id());
+                    pf.fill_map(state, &state.units[selected_unit_id]);
+                    self.walkable_mesh = Some(
+                        build_walkable_mesh(pf, &context.shader));
+                    self.selection_manager.move_selection_marker(state, scene);
+                },
+                None => {},
+            }
+            update_stacks_layout(scene, state);
+            update_tile_effect_nodes(
+                scene, state, &self.mesh_ids.tile_effect_mesh_ids);
+            update_minefield_nodes(scene, state, self.mesh_ids.mine_mesh_id);
+            update_fortification_nodes(
+                scene, state, self.mesh_ids.fortification_mesh_id);
+            self.picker.update_units(state);
+        }
+        self.update_group_mesh(context);
+        self.update_command_radius_mesh(context);
+        self.update_unit_info(context);
+        self.update_orders_mesh(context);
     }
 }
 
@@ -660,6 +1884,7 @@ impl StateVisualizer for GameStateVisualizer {
     fn draw(&mut self, context: &Context, dtime: Time) {
         use glfw::Context;
         self.pick_tile(context);
+        self.update_odds_tooltip(context);
         mgl::set_clear_color(mgl::GREY_3);
         mgl::clear_screen();
         context.shader.activate();
@@ -667,6 +1892,12 @@ impl StateVisualizer for GameStateVisualizer {
         context.shader.uniform_color(context.basic_color_id, mgl::BLACK);
         self.draw_3d_text(context);
         self.button_manager.draw(context);
+        match self.buy_menu {
+            Some(ref buy_menu) => buy_menu.button_manager.draw(context),
+            None => {},
+        }
+        self.unit_info.draw(context);
+        self.odds_tooltip.draw(context);
         context.win.swap_buffers();
     }
 
@@ -674,13 +1905,27 @@ impl StateVisualizer for GameStateVisualizer {
         match event {
             glfw::KeyEvent(key, _, glfw::Press, _) => {
                 self.handle_key_event(context, key);
+                self.update_orders_mesh(context);
             },
             glfw::CursorPosEvent(x, y) => {
                 let p = ScreenPos{v: Vector2{x: x as MInt, y: y as MInt}};
                 self.handle_cursor_pos_event(context, p);
             },
             glfw::MouseButtonEvent(glfw::MouseButtonLeft, glfw::Press, _) => {
-                self.handle_mouse_button_event(context);
+                self.drag_start_pos = Some(context.mouse_pos);
+            },
+            // Click is handled on release because it can be a box selection
+            glfw::MouseButtonEvent(glfw::MouseButtonLeft, glfw::Release, _) => {
+                let start_pos = self.drag_start_pos.take();
+                match start_pos {
+                    Some(pos) if self.event_visualizer.is_none()
+                        && is_drag(pos, context.mouse_pos) =>
+                    {
+                        self.select_units_in_box(context, pos, context.mouse_pos);
+                    },
+                    _ => self.handle_mouse_button_event(context),
+                }
+                self.update_orders_mesh(context);
             },
             glfw::SizeEvent(w, h) => {
                 self.camera.regenerate_projection_mat(Size2{w: w, h: h});
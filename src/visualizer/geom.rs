@@ -55,6 +55,32 @@ pub fn index_to_hex_vertex_s(scale: MFloat, i: MInt) -> VertexCoord {
     VertexCoord{v: v}
 }
 
+// Stacked units share hex triangles: every unit gets
+// continuous sector of the hex for picking and placement
+pub fn hex_triangle_to_stack_index(count: MInt, triangle: MInt) -> MInt {
+    triangle * count / 6
+}
+
+pub fn get_stack_offset(count: MInt, index: MInt) -> Vector3<MFloat> {
+    let mut offset = Vector3{x: 0.0, y: 0.0, z: 0.0};
+    if count <= 1 {
+        return offset;
+    }
+    let mut triangles_count = 0i32;
+    for triangle in range(0i32, 6) {
+        if hex_triangle_to_stack_index(count, triangle) == index {
+            let vertex = index_to_hex_vertex(triangle).v;
+            let next_vertex = index_to_hex_vertex(triangle + 1).v;
+            offset.add_self_v(&vertex.add_v(&next_vertex));
+            triangles_count += 1;
+        }
+    }
+    if triangles_count == 0 {
+        return offset;
+    }
+    offset.mul_s(0.25 / triangles_count as MFloat)
+}
+
 pub fn dist(a: WorldPos, b: WorldPos) -> MFloat {
     let dx = abs(b.v.x - a.v.x);
     let dy = abs(b.v.y - a.v.y);
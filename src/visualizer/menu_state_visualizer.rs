@@ -2,6 +2,8 @@
 
 use glfw;
 use cgmath::{Vector2};
+use core::fs::FileSystem;
+use core::campaign::is_campaign_available;
 use visualizer::mgl;
 use visualizer::types::{Time, ScreenPos};
 use visualizer::gui::{ButtonManager, Button, ButtonId};
@@ -10,24 +12,46 @@ use visualizer::state_visualizer::{
     StateVisualizer,
     StateChangeCommand,
     StartGame,
+    StartSimultaneousGame,
     QuitMenu,
+    OpenCampaign,
 };
 
 pub struct MenuStateVisualizer {
     button_manager: ButtonManager,
     button_start_id: ButtonId,
+    button_simultaneous_id: ButtonId,
+    button_campaign_id: ButtonId,
     button_quit_id: ButtonId,
+    is_campaign_available: bool,
     commands_rx: Receiver<StateChangeCommand>,
     commands_tx: Sender<StateChangeCommand>,
 }
 
 impl MenuStateVisualizer {
-    pub fn new(context: &Context) -> MenuStateVisualizer {
+    pub fn new(fs: &FileSystem, context: &Context) -> MenuStateVisualizer {
+        let is_campaign_available = is_campaign_available(fs);
         let mut button_manager = ButtonManager::new();
         let button_start_id = button_manager.add_button(Button::new(
             "start",
             context.font_stash.borrow_mut().deref_mut(),
             &context.shader,
+            ScreenPos{v: Vector2{x: 10, y: 100}})
+        );
+        let button_simultaneous_id = button_manager.add_button(Button::new(
+            "simultaneous turns",
+            context.font_stash.borrow_mut().deref_mut(),
+            &context.shader,
+            ScreenPos{v: Vector2{x: 10, y: 70}})
+        );
+        let button_campaign_id = button_manager.add_button(Button::new(
+            if is_campaign_available {
+                "campaign"
+            } else {
+                "campaign (no data/campaign.json)"
+            },
+            context.font_stash.borrow_mut().deref_mut(),
+            &context.shader,
             ScreenPos{v: Vector2{x: 10, y: 40}})
         );
         let button_quit_id = button_manager.add_button(Button::new(
@@ -40,17 +64,30 @@ impl MenuStateVisualizer {
         MenuStateVisualizer {
             button_manager: button_manager,
             button_start_id: button_start_id,
+            button_simultaneous_id: button_simultaneous_id,
+            button_campaign_id: button_campaign_id,
             button_quit_id: button_quit_id,
+            is_campaign_available: is_campaign_available,
             commands_rx: commands_rx,
             commands_tx: commands_tx,
         }
     }
 
+    fn open_campaign(&mut self) {
+        if self.is_campaign_available {
+            self.commands_tx.send(OpenCampaign);
+        }
+    }
+
     fn handle_mouse_button_event(&mut self, context: &Context) {
         match self.button_manager.get_clicked_button_id(context) {
             Some(button_id) => {
                 if button_id == self.button_start_id {
                     self.commands_tx.send(StartGame);
+                } else if button_id == self.button_simultaneous_id {
+                    self.commands_tx.send(StartSimultaneousGame);
+                } else if button_id == self.button_campaign_id {
+                    self.open_campaign();
                 } else if button_id == self.button_quit_id {
                     self.commands_tx.send(QuitMenu);
                 }
@@ -80,6 +117,12 @@ impl StateVisualizer for MenuStateVisualizer {
                     glfw::Key1 => {
                         self.commands_tx.send(StartGame);
                     },
+                    glfw::Key2 => {
+                        self.open_campaign();
+                    },
+                    glfw::Key3 => {
+                        self.commands_tx.send(StartSimultaneousGame);
+                    },
                     glfw::KeyEscape | glfw::KeyQ => {
                         self.commands_tx.send(QuitMenu);
                     },
@@ -6,7 +6,7 @@ use std;
 use gl;
 use gl::types::{GLuint, GLsizeiptr};
 use cgmath::{Matrix, Matrix4, Matrix3, ToMatrix4};
-use cgmath::{Vector3, rad, ortho};
+use cgmath::{Vector2, Vector3, Vector4, rad, ortho};
 use core::misc::deg_to_rad;
 use core::types::{Size2, MInt};
 use visualizer::types::{MFloat, Color3, Color4, ScreenPos};
@@ -17,6 +17,8 @@ pub const GREY_3: Color3 = Color3{r: 0.3, g: 0.3, b: 0.3};
 pub const BLACK_3: Color3 = Color3{r: 0.0, g: 0.0, b: 0.0};
 pub const WHITE: Color4 = Color4{r: 1.0, g: 1.0, b: 1.0, a: 1.0};
 pub const BLUE: Color4 = Color4{r: 0.0, g: 0.0, b: 1.0, a: 1.0};
+pub const RED: Color4 = Color4{r: 1.0, g: 0.0, b: 0.0, a: 1.0};
+pub const GREEN: Color4 = Color4{r: 0.0, g: 1.0, b: 0.0, a: 1.0};
 pub const BLACK: Color4 = Color4{r: 0.0, g: 0.0, b: 0.0, a: 1.0};
 
 macro_rules! verify(
@@ -62,6 +64,21 @@ pub fn tr(m: Matrix4<MFloat>, v: Vector3<MFloat>) -> Matrix4<MFloat> {
     m.mul_m(&t)
 }
 
+// None if the point is behind the camera
+pub fn world_to_screen_pos(
+    m: Matrix4<MFloat>,
+    win_size: Size2<MInt>,
+    v: Vector3<MFloat>,
+) -> Option<ScreenPos> {
+    let p = m.mul_v(&Vector4{x: v.x, y: v.y, z: v.z, w: 1.0});
+    if p.w <= 0.0 {
+        return None;
+    }
+    let x = (p.x / p.w + 1.0) / 2.0 * win_size.w as MFloat;
+    let y = (1.0 - p.y / p.w) / 2.0 * win_size.h as MFloat;
+    Some(ScreenPos{v: Vector2{x: x as MInt, y: y as MInt}})
+}
+
 pub fn scale(m: Matrix4<MFloat>, scale: MFloat) -> Matrix4<MFloat> {
     let mut t = Matrix4::<MFloat>::identity();
     t[0][0] = scale;
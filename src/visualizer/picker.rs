@@ -33,22 +33,17 @@ pub struct TilePicker {
     map_size: Size2<MInt>,
 }
 
-fn tile_color(state: &GameState, pos: MapPos) -> Color3 {
-    let mut unit = None;
-    for (_, unit2) in state.units.iter() {
-        if unit2.pos == pos {
-            unit = Some(unit2);
-        }
-    }
-    match unit {
-        Some(unit) => {
-            Color3{r: i_to_f(unit.id.id), g: 0.0, b: i_to_f(PICK_CODE_UNIT)}
-        },
-        None => {
-            let col_x = i_to_f(pos.v.x);
-            let col_y = i_to_f(pos.v.y);
-            Color3{r: col_x, g: col_y, b: i_to_f(PICK_CODE_MAP_POS)}
-        },
+fn tile_color(state: &GameState, pos: MapPos, triangle: MInt) -> Color3 {
+    let units = state.units_at(pos);
+    let count = units.len() as MInt;
+    if count == 0 {
+        let col_x = i_to_f(pos.v.x);
+        let col_y = i_to_f(pos.v.y);
+        Color3{r: col_x, g: col_y, b: i_to_f(PICK_CODE_MAP_POS)}
+    } else {
+        let index = geom::hex_triangle_to_stack_index(count, triangle);
+        let unit = units[index as uint];
+        Color3{r: i_to_f(unit.id.id), g: 0.0, b: i_to_f(PICK_CODE_UNIT)}
     }
 }
 
@@ -60,7 +55,7 @@ fn get_mesh(state: &GameState, map_size: Size2<MInt>, shader: &Shader) -> Mesh {
         for num in range(0i32, 6) {
             let vertex = geom::index_to_hex_vertex(num);
             let next_vertex = geom::index_to_hex_vertex(num + 1);
-            let color = tile_color(state, tile_pos);
+            let color = tile_color(state, tile_pos, num);
             v_data.push(VertexCoord{v: pos3d.v + vertex.v});
             c_data.push(color);
             v_data.push(VertexCoord{v: pos3d.v + next_vertex.v});
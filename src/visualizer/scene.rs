@@ -11,6 +11,15 @@ pub const MIN_MARKER_NODE_ID: NodeId = NodeId{id: MAX_UNIT_NODE_ID.id + 1};
 pub const MAX_MARKER_NODE_ID: NodeId = NodeId{id: MAX_UNIT_NODE_ID.id * 2};
 pub const SHELL_NODE_ID: NodeId = NodeId{id: MAX_MARKER_NODE_ID.id + 1};
 pub const SELECTION_NODE_ID: NodeId = NodeId{id: SHELL_NODE_ID.id + 1};
+pub const MIN_OBJECTIVE_NODE_ID: NodeId = NodeId{id: SELECTION_NODE_ID.id + 1};
+pub const MAX_OBJECTIVE_NODE_ID: NodeId = NodeId{id: MIN_OBJECTIVE_NODE_ID.id + 100};
+pub const MIN_TILE_EFFECT_NODE_ID: NodeId = NodeId{id: MAX_OBJECTIVE_NODE_ID.id + 1};
+pub const MAX_TILE_EFFECT_NODE_ID: NodeId = NodeId{id: MIN_TILE_EFFECT_NODE_ID.id + 1000};
+pub const MIN_MINEFIELD_NODE_ID: NodeId = NodeId{id: MAX_TILE_EFFECT_NODE_ID.id + 1};
+pub const MAX_MINEFIELD_NODE_ID: NodeId = NodeId{id: MIN_MINEFIELD_NODE_ID.id + 1000};
+pub const MIN_FORTIFICATION_NODE_ID: NodeId = NodeId{id: MAX_MINEFIELD_NODE_ID.id + 1};
+pub const MAX_FORTIFICATION_NODE_ID: NodeId = NodeId{
+    id: MIN_FORTIFICATION_NODE_ID.id + MAX_UNIT_NODE_ID.id};
 
 #[deriving(PartialOrd, Ord, PartialEq, Eq, Hash)]
 pub struct NodeId{pub id: MInt}
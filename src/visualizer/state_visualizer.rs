@@ -6,8 +6,12 @@ use visualizer::context::Context;
 
 pub enum StateChangeCommand {
     StartGame,
+    StartSimultaneousGame,
     QuitMenu,
     EndGame,
+    OpenCampaign,
+    StartCampaignMission,
+    CloseCampaign,
 }
 
 pub trait StateVisualizer {
@@ -15,11 +15,16 @@ use visualizer::context::Context;
 use visualizer::state_visualizer::{
     StateVisualizer,
     StartGame,
+    StartSimultaneousGame,
     EndGame,
     QuitMenu,
+    OpenCampaign,
+    StartCampaignMission,
+    CloseCampaign,
 };
 use visualizer::game_state_visualizer::GameStateVisualizer;
 use visualizer::menu_state_visualizer::MenuStateVisualizer;
+use visualizer::campaign_state_visualizer::CampaignStateVisualizer;
 
 type EventsReceiver = Receiver<(f64, glfw::WindowEvent)>;
 
@@ -75,7 +80,7 @@ impl Visualizer {
             mvp_mat_id: mvp_mat_id,
             basic_color_id: basic_color_id,
         };
-        let visualizer = box MenuStateVisualizer::new(&context);
+        let visualizer = box MenuStateVisualizer::new(&fs, &context);
         Visualizer {
             visualizers: vec![visualizer as Box<StateVisualizer>],
             dtime: Time{n: 0},
@@ -112,7 +117,22 @@ impl Visualizer {
                     &self.fs, &self.context);
                 self.visualizers.push(visualizer as Box<StateVisualizer>);
             }
-            Some(EndGame) => {
+            Some(StartSimultaneousGame) => {
+                let visualizer = box GameStateVisualizer::new_simultaneous(
+                    &self.fs, &self.context);
+                self.visualizers.push(visualizer as Box<StateVisualizer>);
+            },
+            Some(OpenCampaign) => {
+                let visualizer = box CampaignStateVisualizer::new(
+                    &self.fs, &self.context);
+                self.visualizers.push(visualizer as Box<StateVisualizer>);
+            },
+            Some(StartCampaignMission) => {
+                let visualizer = box GameStateVisualizer::new_campaign_mission(
+                    &self.fs, &self.context);
+                self.visualizers.push(visualizer as Box<StateVisualizer>);
+            },
+            Some(EndGame) | Some(CloseCampaign) => {
                 let _ = self.visualizers.pop();
             },
             Some(QuitMenu) => {